@@ -0,0 +1,18 @@
+//! Builds a bug report bundle outside of the game
+//!
+//! Usage: `bug_report [data directory]`, the data directory is resolved the same way as in game
+//! when left out.
+use std::path::PathBuf;
+
+fn main() {
+    if let Some(dir) = std::env::args_os().nth(1) {
+        let _ = randomizer_utilities::paths::set_data_root(PathBuf::from(dir));
+    }
+    match randomizer_utilities::bug_report::create_bug_report() {
+        Ok(path) => println!("Bug report written to {}", path.display()),
+        Err(err) => {
+            eprintln!("Failed to create bug report: {err}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Packs everything needed to look into an issue into a single zip, so users only have to attach
+//! one file.
+//!
+//! The archive holds:
+//! * `logs/`: the current and rotated logs, plus any crash files
+//! * `configs/`: every `.toml` in `archipelago/`
+//! * `room/archipelago.json`: the sync file for the current (or most recent) room
+//! * `versions.json`: the loader status with game and mod versions, when available
+//! * `patches.json`: every registered patch and whether it's applied
+//! * `manifest.json`: when the bundle was made and the timestamps of everything in it
+//!
+//! Everything goes through the redactor before it is zipped, and the room directory name (which
+//! holds the slot name) is replaced with `<room>`. Files that aren't text are left out.
+use crate::logging::redaction::redact;
+use crate::paths;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const BUNDLE_PREFIX: &str = "bug_report_";
+const SYNC_FILE_NAME: &str = "archipelago.json";
+
+static ROOM_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Remembers the room that is currently connected, so its sync file ends up in the bundle
+pub(crate) fn set_room_dir(path: PathBuf) {
+    if let Ok(mut room) = ROOM_DIR.lock() {
+        *room = Some(path);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    /// Path inside the archive
+    pub path: String,
+    /// Where the file was read from
+    pub source: String,
+    /// Last modification of the source file (RFC 3339), if known
+    pub modified: Option<String>,
+    pub size: u64,
+    pub redacted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub created: String,
+    pub data_root: String,
+    pub data_root_source: String,
+    pub entries: Vec<ManifestEntry>,
+    /// Anything that couldn't be added, so a missing file is explained
+    pub skipped: Vec<String>,
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+fn format_time(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time).to_rfc3339()
+}
+
+/// The room that is currently connected, if any
+pub(crate) fn current_room_dir() -> Option<PathBuf> {
+    ROOM_DIR.try_lock().ok()?.clone()
+}
+
+/// The connected room, otherwise whichever room directory had its sync file written last
+fn room_dir() -> Option<PathBuf> {
+    if let Some(room) = current_room_dir() {
+        return Some(room);
+    }
+    fs::read_dir(paths::archipelago_dir())
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter_map(|dir| Some((modified_time(&dir.join(SYNC_FILE_NAME))?, dir)))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, dir)| dir)
+}
+
+struct BundleWriter {
+    zip: ZipWriter<File>,
+    manifest: Manifest,
+    /// Name of the room directory, which isn't a secret the redactor knows about unless that room
+    /// was connected to in this session
+    room_name: Option<String>,
+}
+
+impl BundleWriter {
+    fn redact(&self, text: &str) -> String {
+        let text = redact(text);
+        match &self.room_name {
+            Some(room_name) if !room_name.is_empty() => text.replace(room_name, "<room>"),
+            _ => text,
+        }
+    }
+
+    fn skip(&mut self, source: &Path, reason: &dyn std::fmt::Display) {
+        let message = self.redact(&format!("{}: {}", source.display(), reason));
+        self.manifest.skipped.push(message);
+    }
+
+    fn add_bytes(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.zip.start_file(name, SimpleFileOptions::default())?;
+        self.zip.write_all(data)?;
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &str, source: &Path) {
+        let data = match fs::read(source).map(String::from_utf8) {
+            Ok(Ok(text)) => self.redact(&text).into_bytes(),
+            Ok(Err(_)) => {
+                self.skip(source, &"not a text file, can't be redacted");
+                return;
+            }
+            Err(err) => {
+                self.skip(source, &err);
+                return;
+            }
+        };
+        if let Err(err) = self.add_bytes(name, &data) {
+            self.skip(source, &err);
+            return;
+        }
+        self.manifest.entries.push(ManifestEntry {
+            path: name.to_string(),
+            source: self.redact(&source.display().to_string()),
+            modified: modified_time(source).map(format_time),
+            size: data.len() as u64,
+            redacted: true,
+        });
+    }
+
+    fn add_json<T: Serialize>(&mut self, name: &str, value: &T) -> Result<(), Box<dyn Error>> {
+        let data = self
+            .redact(&serde_json::to_string_pretty(value)?)
+            .into_bytes();
+        self.add_bytes(name, &data)?;
+        self.manifest.entries.push(ManifestEntry {
+            path: name.to_string(),
+            source: "<generated>".to_string(),
+            modified: None,
+            size: data.len() as u64,
+            redacted: true,
+        });
+        Ok(())
+    }
+
+    /// Adds every file in `dir` that `filter` accepts, under `prefix/`
+    fn add_dir<F: Fn(&Path) -> bool>(&mut self, dir: &Path, prefix: &str, filter: F) {
+        let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file() && filter(path))
+                .collect(),
+            Err(err) => {
+                self.skip(dir, &err);
+                return;
+            }
+        };
+        files.sort();
+        for file in files {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            self.add_file(&format!("{prefix}/{name}"), &file);
+        }
+    }
+}
+
+fn pending_path() -> PathBuf {
+    paths::logs_dir().join(format!("{BUNDLE_PREFIX}pending"))
+}
+
+/// Asks for a bundle to be built on the next launch, used after a crash since zipping up the logs
+/// from a crashing process isn't safe
+pub(crate) fn request_bug_report() {
+    let requested = fs::create_dir_all(paths::logs_dir())
+        .and_then(|_| fs::write(pending_path(), chrono::Local::now().to_rfc3339()));
+    if let Err(err) = requested {
+        log::error!("Unable to request a bug report: {}", err);
+    }
+}
+
+/// Builds the bundle requested by a crash in an earlier session, if there is one
+pub fn create_pending_bug_report() -> Option<Result<PathBuf, Box<dyn Error>>> {
+    pending_path().exists().then(|| {
+        log::info!("The previous session crashed, building a bug report");
+        create_bug_report()
+    })
+}
+
+/// Builds a bug report bundle in the logs folder, returning where it was written
+pub fn create_bug_report() -> Result<PathBuf, Box<dyn Error>> {
+    let path = create_bug_report_in(&paths::logs_dir())?;
+    // Whatever a crash asked for is in this one
+    let _ = fs::remove_file(pending_path());
+    Ok(path)
+}
+
+/// Builds a bug report bundle in `output_dir`, returning where it was written
+pub fn create_bug_report_in(output_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(output_dir)?;
+    let now = chrono::Local::now();
+    let path = output_dir.join(format!(
+        "{}{}.zip",
+        BUNDLE_PREFIX,
+        now.format("%Y%m%d_%H%M%S")
+    ));
+    let room = room_dir();
+    let mut writer = BundleWriter {
+        zip: ZipWriter::new(File::create(&path)?),
+        room_name: room
+            .as_ref()
+            .and_then(|room| room.file_name())
+            .map(|name| name.to_string_lossy().to_string()),
+        manifest: Manifest {
+            created: now.to_rfc3339(),
+            data_root: redact(&paths::data_root().display().to_string()),
+            data_root_source: paths::data_root_source().to_string(),
+            entries: Vec::new(),
+            skipped: Vec::new(),
+        },
+    };
+
+    // Earlier bundles would just make every new one bigger
+    writer.add_dir(&paths::logs_dir(), "logs", |file| {
+        !file
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with(BUNDLE_PREFIX))
+    });
+    writer.add_dir(&paths::archipelago_dir(), "configs", |file| {
+        file.extension().is_some_and(|ext| ext == "toml")
+    });
+    match room {
+        Some(room) => writer.add_file(
+            &format!("room/{SYNC_FILE_NAME}"),
+            &room.join(SYNC_FILE_NAME),
+        ),
+        None => writer
+            .manifest
+            .skipped
+            .push(format!("{SYNC_FILE_NAME}: no room found")),
+    }
+    #[cfg(feature = "dmc")]
+    match crate::dmc::loader_parser::LOADER_STATUS.get() {
+        Some(status) => writer.add_json("versions.json", status)?,
+        None => writer
+            .manifest
+            .skipped
+            .push("versions.json: loader status was never set".to_string()),
+    }
+    // try_lock, as this also runs from the crash handler
+    let patches = crate::patches::PATCHES
+        .try_lock()
+        .ok()
+        .map(|patches| patches.status());
+    match patches {
+        Some(patches) => writer.add_json("patches.json", &patches)?,
+        None => writer
+            .manifest
+            .skipped
+            .push("patches.json: patch manager was busy".to_string()),
+    }
+
+    // Source paths can hold the user name
+    let manifest = writer.redact(&serde_json::to_string_pretty(&writer.manifest)?);
+    let mut zip = writer.zip;
+    zip.start_file("manifest.json", SimpleFileOptions::default())?;
+    zip.write_all(manifest.as_bytes())?;
+    zip.finish()?;
+    log::info!("Wrote bug report to {}", path.display());
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn room_names_are_hidden_and_binary_files_skipped() {
+        let dir = paths::test_data_root().join("bug_report_fixture");
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("game.log");
+        fs::write(
+            &log,
+            "Saved sync file to archipelago/1234_SomeSlot/archipelago.json",
+        )
+        .unwrap();
+        let binary = dir.join("crash.dmp");
+        fs::write(&binary, [0xFF, 0xFE, 0x00, 0x80]).unwrap();
+
+        let zip_path = dir.join("bundle.zip");
+        let mut writer = BundleWriter {
+            zip: ZipWriter::new(File::create(&zip_path).unwrap()),
+            manifest: Manifest {
+                created: String::new(),
+                data_root: String::new(),
+                data_root_source: String::new(),
+                entries: Vec::new(),
+                skipped: Vec::new(),
+            },
+            room_name: Some("1234_SomeSlot".to_string()),
+        };
+        writer.add_file("logs/game.log", &log);
+        writer.add_file("logs/crash.dmp", &binary);
+        writer.zip.finish().unwrap();
+
+        assert_eq!(writer.manifest.entries.len(), 1);
+        assert!(writer.manifest.entries[0].redacted);
+        assert_eq!(writer.manifest.skipped.len(), 1);
+        let mut archive = zip::ZipArchive::new(File::open(&zip_path).unwrap()).unwrap();
+        let mut contents = String::new();
+        archive
+            .by_name("logs/game.log")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(
+            contents,
+            "Saved sync file to archipelago/<room>/archipelago.json"
+        );
+    }
+
+    #[test]
+    fn crashes_only_request_a_bundle() {
+        paths::test_data_root();
+        assert!(create_pending_bug_report().is_none());
+        request_bug_report();
+        assert!(pending_path().exists());
+        let path = create_pending_bug_report().unwrap().unwrap();
+        assert!(path.exists());
+        assert!(!pending_path().exists());
+        assert!(create_pending_bug_report().is_none());
+    }
+}
@@ -0,0 +1,318 @@
+//! Layered config loading. Each layer overrides the ones before it:
+//!
+//! 1. Built-in defaults
+//! 2. The global file, `archipelago/<name>.toml`
+//! 3. An optional per-room file, `<room path>/<name>.toml`
+//! 4. Environment variables, `APRANDO_<NAME>_<KEY>` with `__` separating nested keys
+//! 5. Launch arguments, `--aprando.<name>.<key.path>=<value>`
+use crate::config::migration::VERSION_KEY;
+use crate::config::recovery::leaves;
+use figment::providers::{Env, Format, Serialized, Toml};
+use figment::value::{Dict, Map, Value};
+use figment::{Figment, Metadata, Profile, Provider};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::path::Path;
+use toml::Table;
+
+/// Where an effective config value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+pub enum ConfigLayer {
+    #[strum(serialize = "built-in default")]
+    Default,
+    #[strum(serialize = "global config file")]
+    GlobalFile,
+    #[strum(serialize = "room config file")]
+    RoomFile,
+    #[strum(serialize = "environment variable")]
+    Environment,
+    #[strum(serialize = "launch argument")]
+    LaunchArgument,
+}
+
+impl ConfigLayer {
+    const ALL: [ConfigLayer; 5] = [
+        ConfigLayer::Default,
+        ConfigLayer::GlobalFile,
+        ConfigLayer::RoomFile,
+        ConfigLayer::Environment,
+        ConfigLayer::LaunchArgument,
+    ];
+
+    /// Provider name the layer is tagged with, kept apart from the Display text so rewording
+    /// that can't break the lookup
+    fn provider_name(self) -> &'static str {
+        match self {
+            ConfigLayer::Default => "aprando-default",
+            ConfigLayer::GlobalFile => "aprando-global-file",
+            ConfigLayer::RoomFile => "aprando-room-file",
+            ConfigLayer::Environment => "aprando-environment",
+            ConfigLayer::LaunchArgument => "aprando-launch-argument",
+        }
+    }
+
+    fn from_metadata(metadata: &Metadata) -> Option<ConfigLayer> {
+        Self::ALL
+            .into_iter()
+            .find(|layer| layer.provider_name() == metadata.name)
+    }
+}
+
+/// Wraps a provider so its values can be traced back to a [`ConfigLayer`]
+struct Tagged<P> {
+    layer: ConfigLayer,
+    provider: P,
+}
+
+impl<P: Provider> Provider for Tagged<P> {
+    fn metadata(&self) -> Metadata {
+        let mut metadata = self.provider.metadata();
+        metadata.name = self.layer.provider_name().into();
+        metadata
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        self.provider.data()
+    }
+}
+
+/// Picks `--aprando.<name>.<key.path>=<value>` entries out of the launch arguments
+struct LaunchArguments {
+    prefix: String,
+    args: Vec<String>,
+}
+
+impl Provider for LaunchArguments {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("launch arguments")
+    }
+
+    fn data(&self) -> Result<Map<Profile, Dict>, figment::Error> {
+        fn insert(dict: &mut Dict, path: &str, value: Value) {
+            match path.split_once('.') {
+                None => {
+                    dict.insert(path.to_string(), value);
+                }
+                Some((first, rest)) => {
+                    let entry = dict
+                        .entry(first.to_string())
+                        .or_insert_with(|| Dict::new().into());
+                    if entry.as_dict().is_none() {
+                        *entry = Dict::new().into();
+                    }
+                    if let Value::Dict(_, inner) = entry {
+                        insert(inner, rest, value);
+                    }
+                }
+            }
+        }
+
+        let mut dict = Dict::new();
+        for arg in &self.args {
+            if let Some((path, value)) = arg
+                .strip_prefix(&self.prefix)
+                .and_then(|arg| arg.split_once('='))
+            {
+                let value: Value = value.parse().unwrap_or_else(|_| Value::from(value));
+                insert(&mut dict, path, value);
+            }
+        }
+        Ok(Map::from([(Profile::Default, dict)]))
+    }
+}
+
+/// A loaded config along with the providers that made it up
+pub struct LayeredConfig<T> {
+    pub config: T,
+    figment: Figment,
+}
+
+impl<T> LayeredConfig<T> {
+    /// The layer the effective value at `key_path` (I.e "connection.address") came from
+    pub fn source_of(&self, key_path: &str) -> Option<ConfigLayer> {
+        self.figment
+            .find_metadata(key_path)
+            .and_then(ConfigLayer::from_metadata)
+    }
+
+    /// Every effective value in the config and the layer that supplied it
+    pub fn sources(&self) -> Vec<(String, ConfigLayer)> {
+        let Ok(table) = self.figment.extract::<Table>() else {
+            return Vec::new();
+        };
+        leaves(&table)
+            .into_iter()
+            .filter(|(path, _)| path != VERSION_KEY)
+            .filter_map(|(path, _)| self.source_of(&path).map(|layer| (path, layer)))
+            .collect()
+    }
+}
+
+/// Loads `config_name` through every layer. The global file is created, migrated or regenerated
+/// first the same way [`crate::load_config`] would.
+///
+/// `room_path` is the directory from [`crate::get_room_path`], when connected to a room.
+pub fn load_layered_config<T>(
+    config_name: &str,
+    room_path: Option<&str>,
+) -> Result<LayeredConfig<T>, Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    crate::load_config::<T>(config_name)?;
+    let figment = layers::<T>(config_name, room_path, std::env::args().skip(1).collect());
+    let config = figment.extract::<T>()?;
+    Ok(LayeredConfig { config, figment })
+}
+
+fn layers<T>(config_name: &str, room_path: Option<&str>, args: Vec<String>) -> Figment
+where
+    T: Default + Serialize,
+{
+    let mut figment = Figment::new()
+        .merge(Tagged {
+            layer: ConfigLayer::Default,
+            provider: Serialized::defaults(T::default()),
+        })
+        .merge(Tagged {
+            layer: ConfigLayer::GlobalFile,
+            provider: Toml::file(super::archipelago_file(&format!("{}.toml", config_name))),
+        });
+    if let Some(room_path) = room_path {
+        let room_file = Path::new(room_path).join(format!("{}.toml", config_name));
+        if room_file.exists() {
+            log::debug!("Using room overrides from {}", room_file.display());
+            figment = figment.merge(Tagged {
+                layer: ConfigLayer::RoomFile,
+                provider: Toml::file(room_file),
+            });
+        }
+    }
+    figment
+        .merge(Tagged {
+            layer: ConfigLayer::Environment,
+            provider: Env::prefixed(&format!("APRANDO_{}_", config_name.to_uppercase()))
+                .split("__"),
+        })
+        .merge(Tagged {
+            layer: ConfigLayer::LaunchArgument,
+            provider: LaunchArguments {
+                prefix: format!("--aprando.{}.", config_name),
+                args,
+            },
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::fs;
+
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    #[serde(default)]
+    struct Connection {
+        address: String,
+        port: u16,
+        slot: String,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(default)]
+    struct TestConfig {
+        volume: f32,
+        connection: Connection,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                volume: 0.8,
+                connection: Connection::default(),
+            }
+        }
+    }
+
+    fn layered(
+        config_name: &str,
+        global: &str,
+        room: Option<&str>,
+        args: &[&str],
+    ) -> LayeredConfig<TestConfig> {
+        let root = crate::paths::test_data_root();
+        fs::create_dir_all(crate::paths::archipelago_dir()).unwrap();
+        fs::write(
+            crate::config::archipelago_file(&format!("{}.toml", config_name)),
+            global,
+        )
+        .unwrap();
+        let room_dir = root.join("layer_rooms").join(config_name);
+        fs::create_dir_all(&room_dir).unwrap();
+        if let Some(room) = room {
+            fs::write(room_dir.join(format!("{}.toml", config_name)), room).unwrap();
+        }
+        let figment = layers::<TestConfig>(
+            config_name,
+            Some(room_dir.to_str().unwrap()),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        );
+        LayeredConfig {
+            config: figment.extract().unwrap(),
+            figment,
+        }
+    }
+
+    #[test]
+    fn later_layers_win() {
+        // Only read by this test's config name
+        unsafe { std::env::set_var("APRANDO_LAYERS_ORDER_CONNECTION__PORT", "4000") };
+        let layered = layered(
+            "layers_order",
+            "volume = 0.5\n[connection]\naddress = \"global\"\nport = 1000\nslot = \"global\"\n",
+            Some("[connection]\naddress = \"room\"\nport = 2000\n"),
+            &[
+                "--aprando.layers_order.connection.address=argument",
+                "--aprando.other.volume=0.1",
+                "--unrelated",
+            ],
+        );
+        let config = &layered.config;
+        assert_eq!(config.volume, 0.5);
+        assert_eq!(config.connection.slot, "global");
+        assert_eq!(config.connection.port, 4000);
+        assert_eq!(config.connection.address, "argument");
+
+        assert_eq!(layered.source_of("volume"), Some(ConfigLayer::GlobalFile));
+        assert_eq!(
+            layered.source_of("connection.port"),
+            Some(ConfigLayer::Environment)
+        );
+        assert_eq!(
+            layered.source_of("connection.address"),
+            Some(ConfigLayer::LaunchArgument)
+        );
+    }
+
+    #[test]
+    fn sources_cover_every_value() {
+        let layered = layered(
+            "layers_sources",
+            "config_version = 0\n[connection]\nslot = \"global\"\n",
+            Some("[connection]\nport = 2000\n"),
+            &["--aprando.layers_sources.volume=0.25"],
+        );
+        assert_eq!(layered.config.volume, 0.25);
+        let mut sources = layered.sources();
+        sources.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            sources,
+            vec![
+                ("connection.address".to_string(), ConfigLayer::Default),
+                ("connection.port".to_string(), ConfigLayer::RoomFile),
+                ("connection.slot".to_string(), ConfigLayer::GlobalFile),
+                ("volume".to_string(), ConfigLayer::LaunchArgument),
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Schema versioning for config files.
+//!
+//! Every config written by [`crate::load_config`] carries a `config_version` key. Clients register
+//! one migration per version bump, and older files are walked up step by step until they match
+//! the current schema.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{LazyLock, RwLock};
+use toml::{Table, Value};
+
+pub const VERSION_KEY: &str = "config_version";
+
+/// Upgrades a table by exactly one schema version
+pub type MigrationFn = fn(&mut Table) -> Result<(), Box<dyn Error>>;
+
+/// Steps for a single config, keyed by the version they upgrade from
+type MigrationSteps = Vec<(u32, MigrationFn)>;
+
+static MIGRATIONS: LazyLock<RwLock<HashMap<String, MigrationSteps>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// Registers a step that upgrades `config_name` from `from_version` to `from_version + 1`.
+///
+/// Needs to be called before the config is loaded. The current schema version of a config is
+/// one past the highest registered step, or 0 if nothing has been registered.
+pub fn register_migration(config_name: &str, from_version: u32, migration: MigrationFn) {
+    match MIGRATIONS.write() {
+        Ok(mut migrations) => {
+            let steps = migrations.entry(config_name.to_string()).or_default();
+            steps.retain(|(from, _)| *from != from_version);
+            steps.push((from_version, migration));
+            steps.sort_by_key(|(from, _)| *from);
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to register migration {:?}", err);
+        }
+    }
+}
+
+/// The schema version new files for `config_name` are stamped with
+pub fn current_version(config_name: &str) -> u32 {
+    MIGRATIONS
+        .read()
+        .ok()
+        .and_then(|migrations| {
+            migrations
+                .get(config_name)
+                .and_then(|steps| steps.last().map(|(from, _)| from + 1))
+        })
+        .unwrap_or(0)
+}
+
+/// Version stamped into the table, files from before versioning count as 0
+pub fn table_version(table: &Table) -> Result<u32, MigrationError> {
+    match table.get(VERSION_KEY) {
+        None => Ok(0),
+        Some(Value::Integer(version)) => {
+            u32::try_from(*version).map_err(|_| MigrationError::InvalidVersion(version.to_string()))
+        }
+        Some(other) => Err(MigrationError::InvalidVersion(other.to_string())),
+    }
+}
+
+/// Sets the version key, keeping it as the first entry in the table
+pub fn stamp_version(table: &mut Table, version: u32) {
+    table.remove(VERSION_KEY);
+    let mut stamped = Table::new();
+    stamped.insert(VERSION_KEY.to_string(), Value::Integer(version as i64));
+    stamped.extend(std::mem::take(table));
+    *table = stamped;
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    InvalidVersion(String),
+    NewerThanSupported { found: u32, current: u32 },
+    MissingStep { from: u32 },
+    StepFailed { from: u32, err: Box<dyn Error> },
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::InvalidVersion(value) => {
+                write!(f, "{} is not a valid version: {}", VERSION_KEY, value)
+            }
+            MigrationError::NewerThanSupported { found, current } => write!(
+                f,
+                "Config version {} is newer than the supported version {}",
+                found, current
+            ),
+            MigrationError::MissingStep { from } => {
+                write!(f, "No migration registered from version {}", from)
+            }
+            MigrationError::StepFailed { from, err } => {
+                write!(f, "Migration from version {} failed: {}", from, err)
+            }
+        }
+    }
+}
+
+impl Error for MigrationError {}
+
+/// Brings `table` up to the current version of `config_name`.
+///
+/// Returns the version the table started at. The table is only modified if every step succeeds.
+pub fn migrate(config_name: &str, table: &mut Table) -> Result<u32, MigrationError> {
+    let found = table_version(table)?;
+    let current = current_version(config_name);
+    if found > current {
+        return Err(MigrationError::NewerThanSupported { found, current });
+    }
+    if found == current {
+        return Ok(found);
+    }
+    let steps: MigrationSteps = MIGRATIONS
+        .read()
+        .map(|migrations| migrations.get(config_name).cloned().unwrap_or_default())
+        .unwrap_or_default();
+
+    let mut migrated = table.clone();
+    for from in found..current {
+        let Some((_, step)) = steps.iter().find(|(step_from, _)| *step_from == from) else {
+            return Err(MigrationError::MissingStep { from });
+        };
+        step(&mut migrated).map_err(|err| MigrationError::StepFailed { from, err })?;
+        log::debug!(
+            "Migrated {} from version {} to {}",
+            config_name,
+            from,
+            from + 1
+        );
+    }
+    stamp_version(&mut migrated, current);
+    *table = migrated;
+    Ok(found)
+}
+
+// Helpers for writing migrations, paths are dot separated (I.e "connection.address")
+
+/// Removes and returns the value at `path`
+pub fn take_path(table: &mut Table, path: &str) -> Option<Value> {
+    match path.rsplit_once('.') {
+        None => table.remove(path),
+        Some((parent, key)) => match get_path_mut(table, parent)? {
+            Value::Table(parent) => parent.remove(key),
+            _ => None,
+        },
+    }
+}
+
+pub fn get_path_mut<'a>(table: &'a mut Table, path: &str) -> Option<&'a mut Value> {
+    let (first, rest) = match path.split_once('.') {
+        None => return table.get_mut(path),
+        Some(split) => split,
+    };
+    match table.get_mut(first)? {
+        Value::Table(inner) => get_path_mut(inner, rest),
+        _ => None,
+    }
+}
+
+/// Inserts `value` at `path`, creating (or replacing) any tables along the way
+pub fn insert_path(table: &mut Table, path: &str, value: Value) {
+    match path.split_once('.') {
+        None => {
+            table.insert(path.to_string(), value);
+        }
+        Some((first, rest)) => {
+            let entry = table
+                .entry(first.to_string())
+                .or_insert_with(|| Value::Table(Table::new()));
+            if !entry.is_table() {
+                *entry = Value::Table(Table::new());
+            }
+            if let Value::Table(inner) = entry {
+                insert_path(inner, rest, value);
+            }
+        }
+    }
+}
+
+/// Renames or moves a key/table, does nothing if `from` isn't present
+pub fn rename_path(table: &mut Table, from: &str, to: &str) {
+    if let Some(value) = take_path(table, from) {
+        insert_path(table, to, value);
+    }
+}
+
+/// Replaces the value at `path` with the result of `convert`, used when a field changes type
+pub fn convert_path<F>(table: &mut Table, path: &str, convert: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(Value) -> Result<Value, Box<dyn Error>>,
+{
+    if let Some(value) = get_path_mut(table, path) {
+        let old = std::mem::replace(value, Value::Boolean(false));
+        *value = convert(old)?;
+    }
+    Ok(())
+}
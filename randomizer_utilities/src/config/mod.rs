@@ -0,0 +1,323 @@
+use crate::config::migration::stamp_version;
+use crate::paths;
+use figment::Figment;
+use figment::providers::{Format, Toml};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Table;
+
+pub mod layers;
+pub mod migration;
+mod recovery;
+pub mod watcher;
+mod writer;
+
+pub use layers::{ConfigLayer, LayeredConfig, load_layered_config};
+pub use recovery::{ConfigDiagnostic, get_diagnostics};
+pub use writer::save_config;
+
+/// `<data root>/archipelago/<file_name>`
+pub(crate) fn archipelago_file(file_name: &str) -> PathBuf {
+    paths::archipelago_dir().join(file_name)
+}
+
+/// What to do with a config that can't be used as-is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    /// Back the file up and write a new one containing whatever still parses
+    Regenerate,
+    /// Leave the file alone, bad values only fall back to their defaults in memory
+    Partial,
+}
+
+/// Loads or create a config file with the given name and struct.
+///
+/// Files from older schema versions are upgraded with the steps registered through
+/// [`migration::register_migration`]. If the file can't be migrated or read, it is backed up and
+/// regenerated, keeping every value that still fits `T`. Files written by a newer version are
+/// never rewritten, only the values that still fit are used.
+pub fn load_config<T>(config_name: &str) -> Result<T, Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    load(config_name, Recovery::Regenerate).map(|(config, _)| config)
+}
+
+/// Same as [`load_config`], but the file is never written: it isn't created if missing, migrated
+/// in place or regenerated. Only the values that failed fall back to their defaults, and each of
+/// them is reported as a [`ConfigDiagnostic`].
+///
+/// Diagnostics are logged and can be fetched later through [`get_diagnostics`].
+pub fn load_config_partial<T>(
+    config_name: &str,
+) -> Result<(T, Vec<ConfigDiagnostic>), Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    load(config_name, Recovery::Partial)
+}
+
+fn load<T>(
+    config_name: &str,
+    recovery: Recovery,
+) -> Result<(T, Vec<ConfigDiagnostic>), Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    fs::create_dir_all(paths::archipelago_dir())?;
+    let config_path = archipelago_file(&format!("{}.toml", config_name));
+    // Partial loads never write, a missing file is just an error there
+    if !config_path.exists() && recovery == Recovery::Regenerate {
+        log::debug!("Config file not found. Creating a default one.");
+        fs::write(
+            &config_path,
+            toml::to_string(&default_table::<T>(config_name)?)?,
+        )?;
+        watcher::mark_written(&config_path);
+    }
+
+    let mut table = match fs::read_to_string(&config_path)?.parse::<Table>() {
+        Ok(table) => table,
+        Err(err) => {
+            log::warn!("Config is not valid TOML: {err}");
+            let diagnostic = ConfigDiagnostic {
+                config_name: config_name.to_string(),
+                key_path: "<file>".to_string(),
+                value: err.message().to_string(),
+                expected: "valid TOML".to_string(),
+            };
+            return match recovery {
+                Recovery::Regenerate => {
+                    recover::<T>(config_name, &config_path, &Table::new(), recovery)
+                }
+                Recovery::Partial => {
+                    recovery::publish(config_name, std::slice::from_ref(&diagnostic));
+                    Ok((T::default(), vec![diagnostic]))
+                }
+            };
+        }
+    };
+    crate::logging::redaction::add_config_secrets(&table);
+
+    match migration::migrate(config_name, &mut table) {
+        // Partial loads keep the migrated table in memory, the file is upgraded by the next full
+        // load
+        Ok(version)
+            if version != migration::current_version(config_name)
+                && recovery == Recovery::Regenerate =>
+        {
+            let backup_path = archipelago_file(&format!("{}.v{}.toml", config_name, version));
+            fs::copy(&config_path, &backup_path)?;
+            let existing = fs::read_to_string(&config_path)?;
+            fs::write(&config_path, writer::rewrite(&existing, &table)?)?;
+            watcher::mark_written(&config_path);
+            log::info!(
+                "Migrated {} from version {}, previous file kept at {}",
+                config_name,
+                version,
+                backup_path.display()
+            );
+        }
+        Ok(_) => {}
+        // Written by a newer version, which still needs the file as it is
+        Err(err @ migration::MigrationError::NewerThanSupported { .. }) => {
+            log::warn!("{err}, using what still fits without changing the file");
+            return recover::<T>(config_name, &config_path, &table, Recovery::Partial);
+        }
+        Err(err) => {
+            log::warn!("Unable to migrate config: {err}");
+            return recover::<T>(config_name, &config_path, &table, recovery);
+        }
+    }
+
+    match Figment::new()
+        .merge(Toml::string(&toml::to_string(&table)?))
+        .extract::<T>()
+    {
+        Ok(config) => {
+            recovery::publish(config_name, &[]);
+            Ok((config, Vec::new()))
+        }
+        Err(err) => {
+            log::warn!("Failed to parse config: {err}");
+            recover::<T>(config_name, &config_path, &table, recovery)
+        }
+    }
+}
+
+/// Serializes `T::default()` with the current schema version stamped in
+fn default_table<T>(config_name: &str) -> Result<Table, Box<dyn Error>>
+where
+    T: Default + Serialize,
+{
+    let mut table = to_table(&T::default())?;
+    stamp_version(&mut table, migration::current_version(config_name));
+    Ok(table)
+}
+
+/// Goes through the serialized string rather than `Table::try_from`, so `f32` fields keep their
+/// short form instead of being widened (0.8 rather than 0.800000011920929)
+pub(crate) fn to_table<T: Serialize>(value: &T) -> Result<Table, Box<dyn Error>> {
+    Ok(toml::to_string(value)?.parse::<Table>()?)
+}
+
+/// Builds a config out of whatever in `old` still parses. When regenerating, the current file is
+/// backed up and replaced with the result.
+fn recover<T>(
+    config_name: &str,
+    config_path: &Path,
+    old: &Table,
+    recovery: Recovery,
+) -> Result<(T, Vec<ConfigDiagnostic>), Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned,
+{
+    let (table, diagnostics) =
+        recovery::salvage::<T>(config_name, default_table::<T>(config_name)?, old);
+    recovery::publish(config_name, &diagnostics);
+
+    if recovery == Recovery::Regenerate {
+        let backup_path = archipelago_file(&format!("{}.old.toml", config_name));
+        // Unreadable files just get a fresh serialization
+        let existing = fs::read_to_string(config_path).unwrap_or_default();
+        fs::rename(config_path, &backup_path)?;
+        log::info!("Old config backed up to {}", backup_path.display());
+        fs::write(config_path, writer::rewrite(&existing, &table)?)?;
+        watcher::mark_written(config_path);
+    }
+
+    Ok((table.try_into::<T>().unwrap_or_default(), diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct TestConfig {
+        volume: f32,
+        name: String,
+        enabled: bool,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                volume: 0.8,
+                name: "default".to_string(),
+                enabled: true,
+            }
+        }
+    }
+
+    fn config_path(config_name: &str) -> PathBuf {
+        paths::test_data_root();
+        archipelago_file(&format!("{}.toml", config_name))
+    }
+
+    #[test]
+    fn creates_a_default_config() {
+        let path = config_path("smoke_default");
+        let config = load_config::<TestConfig>("smoke_default").unwrap();
+        assert_eq!(config, TestConfig::default());
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(written.contains("volume = 0.8"), "{written}");
+        assert_eq!(load_config::<TestConfig>("smoke_default").unwrap(), config);
+    }
+
+    #[test]
+    fn reads_values_from_the_file() {
+        let path = config_path("smoke_values");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "volume = 0.25\nname = \"custom\"\n").unwrap();
+        let config = load_config::<TestConfig>("smoke_values").unwrap();
+        assert_eq!(config.volume, 0.25);
+        assert_eq!(config.name, "custom");
+        assert!(config.enabled);
+    }
+
+    #[test]
+    fn partial_load_keeps_good_values() {
+        let path = config_path("smoke_partial");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let contents = "volume = \"loud\"\nname = \"kept\"\n";
+        fs::write(&path, contents).unwrap();
+        let (config, diagnostics) = load_config_partial::<TestConfig>("smoke_partial").unwrap();
+        assert_eq!(config.volume, 0.8);
+        assert_eq!(config.name, "kept");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].key_path, "volume");
+        // Partial loads leave the file alone
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn regenerates_invalid_toml() {
+        let path = config_path("smoke_invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "volume = [").unwrap();
+        let config = load_config::<TestConfig>("smoke_invalid").unwrap();
+        assert_eq!(config, TestConfig::default());
+        assert!(archipelago_file("smoke_invalid.old.toml").exists());
+        assert!(fs::read_to_string(&path).unwrap().parse::<Table>().is_ok());
+    }
+
+    fn rename_name(table: &mut Table) -> Result<(), Box<dyn Error>> {
+        migration::rename_path(table, "title", "name");
+        Ok(())
+    }
+
+    #[test]
+    fn migration_keeps_comments() {
+        let path = config_path("smoke_migrate");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            "# Main volume\nvolume = 0.5 # quiet\ntitle = \"old\"\nenabled = false\n",
+        )
+        .unwrap();
+        migration::register_migration("smoke_migrate", 0, rename_name);
+        let config = load_config::<TestConfig>("smoke_migrate").unwrap();
+        assert_eq!(config.name, "old");
+        assert!(!config.enabled);
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(
+            written.contains("# Main volume\nvolume = 0.5 # quiet"),
+            "{written}"
+        );
+        assert!(!written.contains("title"), "{written}");
+        assert!(written.contains("config_version = 1"), "{written}");
+    }
+
+    #[test]
+    fn partial_load_migrates_in_memory_only() {
+        let path = config_path("smoke_migrate_partial");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let contents = "title = \"old\"\n";
+        fs::write(&path, contents).unwrap();
+        migration::register_migration("smoke_migrate_partial", 0, rename_name);
+        let (config, diagnostics) =
+            load_config_partial::<TestConfig>("smoke_migrate_partial").unwrap();
+        assert_eq!(config.name, "old");
+        assert!(diagnostics.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
+
+    #[test]
+    fn newer_configs_are_never_rewritten() {
+        let path = config_path("smoke_newer");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let contents = "config_version = 7\nname = \"from the future\"\nvolume = \"loud\"\n";
+        fs::write(&path, contents).unwrap();
+        let config = load_config::<TestConfig>("smoke_newer").unwrap();
+        assert_eq!(config.name, "from the future");
+        assert_eq!(config.volume, 0.8);
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        assert!(!archipelago_file("smoke_newer.old.toml").exists());
+    }
+}
@@ -0,0 +1,219 @@
+use crate::config::migration::{VERSION_KEY, insert_path};
+use crate::ui::font_handler::{RED, WHITE};
+use crate::ui::overlay_messages::{MessageSegment, MessageType, OverlayMessage, add_message};
+use figment::Figment;
+use figment::error::Kind;
+use figment::providers::Serialized;
+use serde::de::DeserializeOwned;
+use std::fmt::{Display, Formatter};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// A single config value that couldn't be used and was replaced by its default
+#[derive(Debug, Clone)]
+pub struct ConfigDiagnostic {
+    pub config_name: String,
+    pub key_path: String,
+    pub value: String,
+    pub expected: String,
+}
+
+impl Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.toml: {} = {} (expected {})",
+            self.config_name, self.key_path, self.value, self.expected
+        )
+    }
+}
+
+impl ConfigDiagnostic {
+    /// Queues the diagnostic as an overlay notification
+    pub fn show_on_overlay(&self) {
+        add_message(OverlayMessage::new(
+            vec![
+                MessageSegment::new(format!("{}.toml: ", self.config_name), RED),
+                MessageSegment::new(
+                    format!("{} should be {}", self.key_path, self.expected),
+                    WHITE,
+                ),
+            ],
+            Duration::from_secs(10),
+            0.0,
+            0.0,
+            MessageType::Notification,
+        ));
+    }
+}
+
+static DIAGNOSTICS: LazyLock<Mutex<Vec<ConfigDiagnostic>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Every diagnostic from the most recent load of each config
+pub fn get_diagnostics() -> Vec<ConfigDiagnostic> {
+    match DIAGNOSTICS.lock() {
+        Ok(diagnostics) => diagnostics.clone(),
+        Err(err) => {
+            log::error!(
+                "PoisonError upon trying to read config diagnostics {:?}",
+                err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Logs the diagnostics for `config_name`, shows them on the overlay and replaces any stored from
+/// a previous load
+pub(crate) fn publish(config_name: &str, diagnostics: &[ConfigDiagnostic]) {
+    for diagnostic in diagnostics {
+        log::warn!("Using default for {}", diagnostic);
+        diagnostic.show_on_overlay();
+    }
+    match DIAGNOSTICS.lock() {
+        Ok(mut stored) => {
+            stored.retain(|diagnostic| diagnostic.config_name != config_name);
+            stored.extend_from_slice(diagnostics);
+        }
+        Err(err) => {
+            log::error!(
+                "PoisonError upon trying to store config diagnostics {:?}",
+                err
+            );
+        }
+    }
+}
+
+/// Collects every non-table value in `table` along with its dotted path
+pub(crate) fn leaves(table: &Table) -> Vec<(String, Value)> {
+    fn walk(prefix: &str, table: &Table, out: &mut Vec<(String, Value)>) {
+        for (key, value) in table {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            match value {
+                Value::Table(inner) => walk(&path, inner, out),
+                _ => out.push((path, value.clone())),
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk("", table, &mut out);
+    out
+}
+
+fn expected_from_kind(kind: Kind) -> String {
+    match kind {
+        Kind::InvalidType(_, expected)
+        | Kind::InvalidValue(_, expected)
+        | Kind::InvalidLength(_, expected) => expected,
+        Kind::UnknownVariant(_, variants) => format!("one of {:?}", variants),
+        other => other.to_string(),
+    }
+}
+
+/// Lays the values from `file` over `base` one at a time, only keeping a value if `T` still
+/// deserializes with it in place.
+///
+/// Returns the merged table, which is `base` at worst, along with a diagnostic for every value
+/// that had to be dropped.
+pub(crate) fn salvage<T>(
+    config_name: &str,
+    base: Table,
+    file: &Table,
+) -> (Table, Vec<ConfigDiagnostic>)
+where
+    T: DeserializeOwned,
+{
+    let mut merged = base;
+    let mut diagnostics = Vec::new();
+    for (path, value) in leaves(file) {
+        if path == VERSION_KEY {
+            continue;
+        }
+        let mut candidate = merged.clone();
+        insert_path(&mut candidate, &path, value.clone());
+        match Figment::from(Serialized::defaults(&candidate)).extract::<T>() {
+            Ok(_) => merged = candidate,
+            Err(err) => diagnostics.push(ConfigDiagnostic {
+                config_name: config_name.to_string(),
+                key_path: path,
+                value: value.to_string(),
+                expected: expected_from_kind(err.kind),
+            }),
+        }
+    }
+    (merged, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Connection {
+        address: String,
+        port: u16,
+    }
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct SalvageConfig {
+        volume: f32,
+        connection: Connection,
+    }
+
+    fn table(text: &str) -> Table {
+        text.parse().unwrap()
+    }
+
+    fn base() -> Table {
+        table("config_version = 1\nvolume = 0.8\n[connection]\naddress = \"\"\nport = 0\n")
+    }
+
+    #[test]
+    fn nested_values_are_kept_one_by_one() {
+        let file = table("[connection]\naddress = \"archipelago.gg\"\nport = \"not a port\"\n");
+        let (merged, diagnostics) = salvage::<SalvageConfig>("salvage", base(), &file);
+        let config: SalvageConfig = merged.try_into().unwrap();
+        assert_eq!(config.volume, 0.8);
+        assert_eq!(config.connection.address, "archipelago.gg");
+        assert_eq!(config.connection.port, 0);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].key_path, "connection.port");
+        assert_eq!(diagnostics[0].value, "\"not a port\"");
+    }
+
+    #[test]
+    fn type_mismatches_fall_back_to_the_base() {
+        let file = table("volume = \"loud\"\n[connection]\nport = 70000\n");
+        let (merged, diagnostics) = salvage::<SalvageConfig>("salvage", base(), &file);
+        assert_eq!(merged, base());
+        let paths: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.key_path.as_str())
+            .collect();
+        assert_eq!(paths, ["volume", "connection.port"]);
+        assert!(
+            diagnostics
+                .iter()
+                .all(|diagnostic| diagnostic.config_name == "salvage")
+        );
+    }
+
+    #[test]
+    fn unknown_keys_and_the_version_are_carried_over_silently() {
+        let file = table("config_version = 0\nfuture_option = true\n[connection]\nretries = 3\n");
+        let (merged, diagnostics) = salvage::<SalvageConfig>("salvage", base(), &file);
+        assert!(diagnostics.is_empty());
+        assert_eq!(merged["config_version"].as_integer(), Some(1));
+        assert_eq!(merged["future_option"].as_bool(), Some(true));
+        assert_eq!(merged["connection"]["retries"].as_integer(), Some(3));
+    }
+}
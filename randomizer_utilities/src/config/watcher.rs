@@ -0,0 +1,339 @@
+//! Opt-in hot reloading for configs in `archipelago/`.
+//!
+//! Configs are registered with [`watch_config`] and the polling thread is started with
+//! [`start_config_watcher`], which lists `archipelago/` on every poll so files that are created
+//! (or replaced) after startup are picked up too. When a watched file changes it is re-parsed and
+//! validated, and only a config that passes both is handed to subscribers. Anything else is
+//! reported as a [`ConfigDiagnostic`] and the last good config stays in effect.
+//!
+//! Reloads only ever read: an old file is migrated in memory, and a broken one is left for the
+//! user to fix. Files written by this crate (I.e [`crate::save_config`]) are recorded through
+//! [`mark_written`] and don't trigger a reload.
+use crate::config::{ConfigDiagnostic, load_config, load_config_partial, recovery};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+type AnyConfig = Arc<dyn Any + Send + Sync>;
+type Reloader = Arc<dyn Fn() -> Result<AnyConfig, Vec<ConfigDiagnostic>> + Send + Sync>;
+type Subscriber = Arc<dyn Fn(&(dyn Any + Send + Sync)) + Send + Sync>;
+
+struct WatchedConfig {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    reload: Reloader,
+    current: AnyConfig,
+    subscribers: Vec<Subscriber>,
+}
+
+static WATCHED: LazyLock<Mutex<HashMap<String, WatchedConfig>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static WATCHER_STARTED: AtomicBool = AtomicBool::new(false);
+/// Modification times of the files this crate wrote itself
+static WRITTEN: LazyLock<Mutex<HashMap<PathBuf, SystemTime>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Records that `path` was just written by this crate, so the change isn't reloaded
+pub(crate) fn mark_written(path: &Path) {
+    if let Some(modified) = modified_time(path)
+        && let Ok(mut written) = WRITTEN.lock()
+    {
+        written.insert(path.to_path_buf(), modified);
+    }
+}
+
+fn written_by_us(path: &Path, modified: SystemTime) -> bool {
+    WRITTEN
+        .lock()
+        .is_ok_and(|written| written.get(path) == Some(&modified))
+}
+
+/// Every `.toml` file in `archipelago/` with its modification time
+fn list_configs() -> HashMap<PathBuf, SystemTime> {
+    let Ok(entries) = fs::read_dir(crate::paths::archipelago_dir()) else {
+        return HashMap::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| Some((modified_time(&path)?, path)))
+        .map(|(modified, path)| (path, modified))
+        .collect()
+}
+
+/// Loads `config_name` and keeps it up to date while the watcher is running.
+///
+/// `validate` runs on every reload after parsing, returning an `Err` rejects the new file.
+pub fn watch_config<T>(
+    config_name: &str,
+    validate: fn(&T) -> Result<(), String>,
+) -> Result<Arc<T>, Box<dyn Error>>
+where
+    T: Default + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    let config = Arc::new(load_config::<T>(config_name)?);
+    let path = super::archipelago_file(&format!("{}.toml", config_name));
+    let name = config_name.to_string();
+    let reload: Reloader = Arc::new(move || {
+        let (config, diagnostics) = load_config_partial::<T>(&name).map_err(|err| {
+            let diagnostic = ConfigDiagnostic {
+                config_name: name.clone(),
+                key_path: "<file>".to_string(),
+                value: err.to_string(),
+                expected: "a readable config".to_string(),
+            };
+            recovery::publish(&name, std::slice::from_ref(&diagnostic));
+            vec![diagnostic]
+        })?;
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+        if let Err(err) = validate(&config) {
+            let diagnostic = ConfigDiagnostic {
+                config_name: name.clone(),
+                key_path: "<config>".to_string(),
+                value: err,
+                expected: "a valid config".to_string(),
+            };
+            recovery::publish(&name, std::slice::from_ref(&diagnostic));
+            return Err(vec![diagnostic]);
+        }
+        Ok(Arc::new(config) as AnyConfig)
+    });
+
+    match WATCHED.lock() {
+        Ok(mut watched) => {
+            let subscribers = watched
+                .remove(config_name)
+                .map(|old| old.subscribers)
+                .unwrap_or_default();
+            watched.insert(
+                config_name.to_string(),
+                WatchedConfig {
+                    modified: modified_time(&path),
+                    path,
+                    reload,
+                    current: config.clone(),
+                    subscribers,
+                },
+            );
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to watch config {:?}", err);
+        }
+    }
+    Ok(config)
+}
+
+/// Calls `callback` with the new value every time `config_name` is successfully reloaded
+pub fn subscribe<T, F>(config_name: &str, callback: F)
+where
+    T: 'static,
+    F: Fn(&T) + Send + Sync + 'static,
+{
+    let subscriber: Subscriber = Arc::new(move |config: &(dyn Any + Send + Sync)| {
+        if let Some(config) = config.downcast_ref::<T>() {
+            callback(config);
+        }
+    });
+    match WATCHED.lock() {
+        Ok(mut watched) => match watched.get_mut(config_name) {
+            Some(entry) => entry.subscribers.push(subscriber),
+            None => log::warn!("Cannot subscribe to {config_name}, it is not being watched"),
+        },
+        Err(err) => {
+            log::error!("PoisonError upon trying to subscribe to config {:?}", err);
+        }
+    }
+}
+
+/// The last good value of a watched config
+pub fn current_config<T>(config_name: &str) -> Option<Arc<T>>
+where
+    T: Send + Sync + 'static,
+{
+    let current = WATCHED.lock().ok()?.get(config_name)?.current.clone();
+    current.downcast::<T>().ok()
+}
+
+/// Starts polling the watched configs for changes, does nothing if already running
+pub fn start_config_watcher(interval: Duration) {
+    if WATCHER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            check_for_changes();
+        }
+    });
+    log::debug!("Started config watcher");
+}
+
+/// Reloads every watched config whose file changed since it was last seen
+///
+/// Reloads and subscribers run without holding the watch list, so they're free to use
+/// [`current_config`] or watch other configs.
+pub fn check_for_changes() {
+    let files = list_configs();
+
+    let mut changed = Vec::new();
+    match WATCHED.lock() {
+        Ok(mut watched) => {
+            for (name, entry) in watched.iter_mut() {
+                // Deleted files keep the last good config
+                let Some(modified) = files.get(&entry.path).copied() else {
+                    continue;
+                };
+                if entry.modified == Some(modified) {
+                    continue;
+                }
+                entry.modified = Some(modified);
+                if written_by_us(&entry.path, modified) {
+                    continue;
+                }
+                changed.push((name.clone(), entry.reload.clone()));
+            }
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to check configs {:?}", err);
+            return;
+        }
+    }
+
+    let mut reloaded = Vec::new();
+    for (name, reload) in changed {
+        match reload() {
+            Ok(config) => reloaded.push((name, config)),
+            // The diagnostics were already logged and shown on the overlay when published
+            Err(_) => log::warn!("Rejected changes to {name}, keeping the previous config"),
+        }
+    }
+    if reloaded.is_empty() {
+        return;
+    }
+
+    let mut updates = Vec::new();
+    match WATCHED.lock() {
+        Ok(mut watched) => {
+            for (name, config) in reloaded {
+                if let Some(entry) = watched.get_mut(&name) {
+                    log::info!("Reloaded config {name}");
+                    entry.current = config.clone();
+                    updates.push((config, entry.subscribers.clone()));
+                }
+            }
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to update configs {:?}", err);
+        }
+    }
+    for (config, subscribers) in updates {
+        for subscriber in subscribers {
+            subscriber(config.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::migration;
+    use serde::Deserialize;
+    use std::fs::File;
+    use std::sync::atomic::AtomicUsize;
+    use toml::Table;
+
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    struct WatchedTestConfig {
+        speed: i64,
+    }
+
+    /// check_for_changes reloads every watched config, tests using it take turns
+    static CHECKS: Mutex<()> = Mutex::new(());
+
+    fn no_validation(_: &WatchedTestConfig) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn add_speed(table: &mut Table) -> Result<(), Box<dyn Error>> {
+        table.entry("speed").or_insert(toml::Value::Integer(1));
+        Ok(())
+    }
+
+    /// Writes `contents` with a modification time that can't collide with the previous write
+    fn write_with_time(path: &Path, contents: &str, seconds: u64) {
+        fs::write(path, contents).unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    #[test]
+    fn reloads_edits_but_not_its_own_rewrites() {
+        let _checks = CHECKS.lock().unwrap();
+        crate::paths::test_data_root();
+        let name = "watcher_reload";
+        let path = crate::config::archipelago_file(&format!("{name}.toml"));
+        migration::register_migration(name, 0, add_speed);
+        watch_config::<WatchedTestConfig>(name, no_validation).unwrap();
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        subscribe::<WatchedTestConfig, _>(name, move |config| {
+            // Would deadlock if subscribers ran while the watch list is locked
+            let current = current_config::<WatchedTestConfig>(name).unwrap();
+            assert_eq!(current.speed, config.speed);
+            CALLS.fetch_add(1, Ordering::SeqCst);
+        });
+
+        write_with_time(&path, "config_version = 1\nspeed = 5\n", 1_000_000);
+        check_for_changes();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        assert_eq!(current_config::<WatchedTestConfig>(name).unwrap().speed, 5);
+
+        // An old file is migrated for the reload, but left as it is on disk
+        write_with_time(&path, "# old file\n", 2_000_000);
+        check_for_changes();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+        assert_eq!(current_config::<WatchedTestConfig>(name).unwrap().speed, 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# old file\n");
+
+        // Saving is our own write, which isn't a new edit
+        crate::save_config(name, &WatchedTestConfig { speed: 9 }).unwrap();
+        check_for_changes();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn picks_up_files_created_after_watching() {
+        let _checks = CHECKS.lock().unwrap();
+        crate::paths::test_data_root();
+        let name = "watcher_created";
+        let path = crate::config::archipelago_file(&format!("{name}.toml"));
+        watch_config::<WatchedTestConfig>(name, no_validation).unwrap();
+        fs::remove_file(&path).unwrap();
+        check_for_changes();
+        assert_eq!(current_config::<WatchedTestConfig>(name).unwrap().speed, 0);
+
+        write_with_time(&path, "speed = 3\n", 3_000_000);
+        check_for_changes();
+        assert_eq!(current_config::<WatchedTestConfig>(name).unwrap().speed, 3);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::config::migration::stamp_version;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use toml::{Table, Value};
+use toml_edit::{DocumentMut, InlineTable, Item, TableLike};
+
+/// Writes `config` back to `archipelago/<name>.toml`.
+///
+/// Only keys whose value actually changed are touched, so comments, key order and keys `T`
+/// doesn't know about are all kept. Keys that `T` no longer serializes (I.e an `Option` set to
+/// `None`) are left as they are in the file.
+pub fn save_config<T>(config_name: &str, config: &T) -> Result<(), Box<dyn Error>>
+where
+    T: Serialize,
+{
+    let config_path = super::archipelago_file(&format!("{}.toml", config_name));
+    fs::create_dir_all(crate::paths::archipelago_dir())?;
+    let mut new = super::to_table(config)?;
+    stamp_version(&mut new, super::migration::current_version(config_name));
+
+    let existing = if config_path.exists() {
+        fs::read_to_string(&config_path)?
+    } else {
+        String::new()
+    };
+    let Ok(mut document) = existing.parse::<DocumentMut>() else {
+        log::warn!(
+            "Existing {} could not be parsed, overwriting it",
+            config_path.display()
+        );
+        fs::write(&config_path, toml::to_string(&new)?)?;
+        super::watcher::mark_written(&config_path);
+        return Ok(());
+    };
+    let old = existing.parse::<Table>().unwrap_or_default();
+
+    let changed = update_table(document.as_table_mut(), Some(&old), &new, false)?;
+    if changed > 0 {
+        fs::write(&config_path, document.to_string())?;
+        super::watcher::mark_written(&config_path);
+        log::debug!(
+            "Saved {} changed value(s) to {}",
+            changed,
+            config_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// `existing` rewritten to hold exactly `table`, keeping the comments and order of every key that
+/// is still there. Falls back to a plain serialization if `existing` isn't valid TOML.
+pub(super) fn rewrite(existing: &str, table: &Table) -> Result<String, Box<dyn Error>> {
+    let Ok(mut document) = existing.parse::<DocumentMut>() else {
+        return Ok(toml::to_string(table)?);
+    };
+    let old = existing.parse::<Table>().unwrap_or_default();
+    remove_missing(document.as_table_mut(), table);
+    update_table(document.as_table_mut(), Some(&old), table, false)?;
+    Ok(document.to_string())
+}
+
+/// Removes every key of `table` that `new` doesn't have, so removed and renamed keys go away
+fn remove_missing(table: &mut dyn TableLike, new: &Table) {
+    let keys: Vec<String> = table.iter().map(|(key, _)| key.to_string()).collect();
+    for key in keys {
+        let Some(value) = new.get(&key) else {
+            table.remove(&key);
+            continue;
+        };
+        let Some(item) = table.get_mut(&key) else {
+            continue;
+        };
+        match value {
+            Value::Table(inner) => {
+                if let Some(existing) = item.as_table_like_mut() {
+                    remove_missing(existing, inner);
+                }
+            }
+            Value::Array(items) => {
+                if let Some(array) = item.as_array_of_tables_mut() {
+                    for (existing, item) in array.iter_mut().zip(items) {
+                        if let Some(item) = item.as_table() {
+                            remove_missing(existing, item);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_array_of_tables(value: &Value) -> bool {
+    value
+        .as_array()
+        .is_some_and(|items| !items.is_empty() && items.iter().all(Value::is_table))
+}
+
+/// Writes every value in `new` that differs from `old` into `table`, returning how many were
+/// written. Keys are walked one level at a time, so keys with dots in them (I.e `"a.b" = 1`) stay
+/// a single key.
+fn update_table(
+    table: &mut dyn TableLike,
+    old: Option<&Table>,
+    new: &Table,
+    inline: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let mut changed = 0;
+    for (key, value) in new {
+        let old_value = old.and_then(|old| old.get(key));
+        if old_value == Some(value) {
+            continue;
+        }
+        match value {
+            Value::Table(inner) => {
+                if table.get(key).and_then(Item::as_table_like).is_none() {
+                    let item = if inline {
+                        Item::Value(InlineTable::new().into())
+                    } else {
+                        let mut inner = toml_edit::Table::new();
+                        inner.set_implicit(true);
+                        Item::Table(inner)
+                    };
+                    table.insert(key, item);
+                }
+                if let Some(item) = table.get_mut(key) {
+                    let inline = inline || item.is_inline_table();
+                    if let Some(existing) = item.as_table_like_mut() {
+                        changed += update_table(
+                            existing,
+                            old_value.and_then(Value::as_table),
+                            inner,
+                            inline,
+                        )?;
+                    }
+                }
+            }
+            // `[[key]]` sections are updated one entry at a time, keeping their comments
+            Value::Array(items)
+                if is_array_of_tables(value)
+                    && table.get(key).is_some_and(Item::is_array_of_tables) =>
+            {
+                let Some(array) = table.get_mut(key).and_then(Item::as_array_of_tables_mut) else {
+                    continue;
+                };
+                let old_items = old_value.and_then(Value::as_array);
+                for (index, item) in items.iter().enumerate() {
+                    let Some(item) = item.as_table() else {
+                        continue;
+                    };
+                    let old_item = old_items
+                        .and_then(|old_items| old_items.get(index))
+                        .and_then(Value::as_table);
+                    match array.get_mut(index) {
+                        Some(existing) => {
+                            changed += update_table(existing, old_item, item, false)?;
+                        }
+                        None => {
+                            let mut entry = toml_edit::Table::new();
+                            changed += update_table(&mut entry, None, item, false)?;
+                            array.push(entry);
+                        }
+                    }
+                }
+                while array.len() > items.len() {
+                    array.remove(array.len() - 1);
+                    changed += 1;
+                }
+            }
+            _ => {
+                set_value(table, key, value.to_string().parse::<toml_edit::Value>()?);
+                changed += 1;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+/// Sets `key` to `value`, keeping the comments and whitespace of any value it replaces
+fn set_value(table: &mut dyn TableLike, key: &str, value: toml_edit::Value) {
+    match table.get_mut(key).and_then(Item::as_value_mut) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value;
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(key, Item::Value(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Binding {
+        action: String,
+        key: String,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct WriterConfig {
+        volume: f32,
+        colors: BTreeMap<String, String>,
+        bindings: Vec<Binding>,
+    }
+
+    fn binding(action: &str, key: &str) -> Binding {
+        Binding {
+            action: action.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn keeps_arrays_of_tables_and_dotted_keys() {
+        crate::paths::test_data_root();
+        let path = super::super::archipelago_file("writer_tables.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(
+            &path,
+            r#"volume = 0.5
+colors = { "menu.text" = "white" }
+
+# Jump binding
+[[bindings]]
+action = "jump"
+key = "space" # default
+
+# Attack binding
+[[bindings]]
+action = "attack"
+key = "j"
+"#,
+        )
+        .unwrap();
+        let mut config = WriterConfig {
+            volume: 0.5,
+            colors: BTreeMap::from([("menu.text".to_string(), "white".to_string())]),
+            bindings: vec![binding("jump", "space"), binding("attack", "j")],
+        };
+        config.bindings[1].key = "k".to_string();
+        config.bindings.push(binding("dash", "shift"));
+        config
+            .colors
+            .insert("menu.text".to_string(), "gold".to_string());
+        save_config("writer_tables", &config).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(
+            written.contains("# Jump binding\n[[bindings]]"),
+            "{written}"
+        );
+        assert!(
+            written.contains("# Attack binding\n[[bindings]]"),
+            "{written}"
+        );
+        assert!(written.contains("key = \"space\" # default"), "{written}");
+        assert!(written.contains("\"menu.text\" = \"gold\""), "{written}");
+        assert!(!written.contains("menu = "), "{written}");
+        let parsed: WriterConfig = toml::from_str(&written).unwrap();
+        assert_eq!(parsed.bindings, config.bindings);
+        assert_eq!(parsed.colors, config.colors);
+
+        config.bindings.truncate(1);
+        save_config("writer_tables", &config).unwrap();
+        let parsed: WriterConfig = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(parsed.bindings, vec![binding("jump", "space")]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Best-effort callbacks for when the process is about to go down, so subsystems can save what
+//! they only hold in memory (I.e offline checks that haven't made it into the sync file yet).
+//!
+//! Callbacks are run from the exception handler on fatal exceptions and from the panic hook. Each
+//! one runs on its own thread and is only waited on for its time budget, since the crashing thread
+//! might be holding a lock the callback needs. A callback panicking is only logged, it doesn't
+//! count as another crash.
+use crate::panic_handler::catch_guarded;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BUDGET: Duration = Duration::from_millis(500);
+
+type Callback = Arc<dyn Fn() + Send + Sync>;
+
+struct CrashCallback {
+    name: String,
+    budget: Duration,
+    callback: Callback,
+}
+
+static CALLBACKS: Mutex<Vec<CrashCallback>> = Mutex::new(Vec::new());
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallbackOutcome {
+    Completed(Duration),
+    Panicked,
+    /// Still running once the budget ran out, it's left to finish on its own
+    TimedOut,
+}
+
+/// Registers `callback` to run on a crash, replacing any callback with the same name
+pub fn register_crash_callback<F>(name: &str, budget: Duration, callback: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    match CALLBACKS.lock() {
+        Ok(mut callbacks) => {
+            callbacks.retain(|existing| existing.name != name);
+            callbacks.push(CrashCallback {
+                name: name.to_string(),
+                budget,
+                callback: Arc::new(callback),
+            });
+        }
+        Err(err) => {
+            log::error!(
+                "PoisonError upon trying to register crash callback {:?}",
+                err
+            );
+        }
+    }
+}
+
+pub fn unregister_crash_callback(name: &str) {
+    if let Ok(mut callbacks) = CALLBACKS.lock() {
+        callbacks.retain(|existing| existing.name != name);
+    }
+}
+
+fn run_one(callback: Callback, budget: Duration) -> CallbackOutcome {
+    let start = Instant::now();
+    let (tx, rx) = mpsc::channel();
+    let worker = std::thread::Builder::new()
+        .name("crash-callback".to_string())
+        .spawn({
+            let callback = callback.clone();
+            move || {
+                let _ = tx.send(catch_guarded(|| callback()));
+            }
+        });
+    let result = match worker {
+        Ok(_) => match rx.recv_timeout(budget) {
+            Ok(result) => result,
+            Err(_) => return CallbackOutcome::TimedOut,
+        },
+        // No thread to spare, so it runs here without a budget
+        Err(_) => catch_guarded(|| callback()),
+    };
+    match result {
+        Ok(()) => CallbackOutcome::Completed(start.elapsed()),
+        Err(_) => CallbackOutcome::Panicked,
+    }
+}
+
+/// Runs every registered callback in registration order, then flushes the log
+///
+/// Does nothing if callbacks are already running (I.e a callback crashed), and skips them
+/// entirely if the registry is locked by the crashing thread.
+pub fn run_crash_callbacks() -> Vec<(String, CallbackOutcome)> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    let callbacks: Vec<(String, Duration, Callback)> = match CALLBACKS.try_lock() {
+        Ok(callbacks) => callbacks
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.budget, entry.callback.clone()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let mut outcomes = Vec::with_capacity(callbacks.len());
+    for (name, budget, callback) in callbacks {
+        let outcome = run_one(callback, budget);
+        match outcome {
+            CallbackOutcome::Completed(took) => {
+                log::info!("Crash callback \"{}\" finished in {:?}", name, took)
+            }
+            CallbackOutcome::Panicked => log::error!("Crash callback \"{}\" panicked", name),
+            CallbackOutcome::TimedOut => {
+                log::error!("Crash callback \"{}\" took longer than {:?}", name, budget)
+            }
+        }
+        outcomes.push((name, outcome));
+    }
+    log::logger().flush();
+    RUNNING.store(false, Ordering::SeqCst);
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn outcomes_cover_completed_panicked_and_timed_out() {
+        assert!(matches!(
+            run_one(Arc::new(|| {}), DEFAULT_BUDGET),
+            CallbackOutcome::Completed(_)
+        ));
+        assert_eq!(
+            run_one(Arc::new(|| panic!("callback")), DEFAULT_BUDGET),
+            CallbackOutcome::Panicked
+        );
+        assert_eq!(
+            run_one(
+                Arc::new(|| std::thread::sleep(Duration::from_millis(200))),
+                Duration::from_millis(10)
+            ),
+            CallbackOutcome::TimedOut
+        );
+    }
+
+    #[test]
+    fn callbacks_run_guarded() {
+        let guarded = Arc::new(AtomicBool::new(false));
+        let seen = guarded.clone();
+        run_one(
+            Arc::new(move || seen.store(crate::panic_handler::is_guarded(), Ordering::SeqCst)),
+            DEFAULT_BUDGET,
+        );
+        assert!(guarded.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn callbacks_run_in_order_and_replace_by_name() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        for (name, value) in [("first", 1), ("second", 2), ("first", 3)] {
+            let calls = calls.clone();
+            register_crash_callback(name, DEFAULT_BUDGET, move || {
+                calls.store(calls.load(Ordering::SeqCst) * 10 + value, Ordering::SeqCst)
+            });
+        }
+        let outcomes = run_crash_callbacks();
+        unregister_crash_callback("first");
+        unregister_crash_callback("second");
+
+        let names: Vec<&str> = outcomes.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["second", "first"]);
+        assert_eq!(calls.load(Ordering::SeqCst), 23);
+        assert!(!RUNNING.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,554 @@
+//! Structured crash reports, written by the exception handler to `logs/crash_<timestamp>.json`.
+//!
+//! Everything in here works on plain values, the Win32 specific capture is in `exception_handler`.
+use crate::exception_handler::exception_code_to_str;
+use crate::logging::ring_buffer::{LogEntry, LogQuery, try_query_logs};
+use crate::platform::LoadedModule;
+use serde::{Serialize, Serializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// How many stack slots above RSP are kept in a report
+pub const STACK_WINDOW: usize = 32;
+/// The stack walk gives up after this many frames
+pub const MAX_FRAMES: usize = 64;
+/// How many of the buffered log records go into a report
+const CRASH_CONTEXT_RECORDS: usize = 50;
+
+static OWN_MODULES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Marks frames in `module_name` (I.e "dinput8.dll" for the loader) as ours in crash reports,
+/// the module this crate is compiled into is always included
+pub fn register_own_module(module_name: &str) {
+    if let Ok(mut modules) = OWN_MODULES.lock()
+        && !modules
+            .iter()
+            .any(|module| module.eq_ignore_ascii_case(module_name))
+    {
+        modules.push(module_name.to_string());
+    }
+}
+
+/// Module names that count as randomizer code
+pub fn own_modules() -> Vec<String> {
+    let mut modules = match OWN_MODULES.try_lock() {
+        Ok(modules) => modules.clone(),
+        Err(_) => Vec::new(),
+    };
+    if let Some(name) = crate::platform::current_module_path()
+        .as_deref()
+        .and_then(Path::file_name)
+    {
+        modules.push(name.to_string_lossy().to_string());
+    }
+    modules
+}
+
+pub(crate) fn hex<T: Into<u64> + Copy, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format!("{:#X}", (*value).into()))
+}
+
+pub(crate) fn hex_usize<S: Serializer>(value: &usize, serializer: S) -> Result<S::Ok, S::Error> {
+    hex(&(*value as u64), serializer)
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Registers {
+    #[serde(serialize_with = "hex")]
+    pub rax: u64,
+    #[serde(serialize_with = "hex")]
+    pub rbx: u64,
+    #[serde(serialize_with = "hex")]
+    pub rcx: u64,
+    #[serde(serialize_with = "hex")]
+    pub rdx: u64,
+    #[serde(serialize_with = "hex")]
+    pub rsi: u64,
+    #[serde(serialize_with = "hex")]
+    pub rdi: u64,
+    #[serde(serialize_with = "hex")]
+    pub rbp: u64,
+    #[serde(serialize_with = "hex")]
+    pub rsp: u64,
+    #[serde(serialize_with = "hex")]
+    pub rip: u64,
+    #[serde(serialize_with = "hex")]
+    pub r8: u64,
+    #[serde(serialize_with = "hex")]
+    pub r9: u64,
+    #[serde(serialize_with = "hex")]
+    pub r10: u64,
+    #[serde(serialize_with = "hex")]
+    pub r11: u64,
+    #[serde(serialize_with = "hex")]
+    pub r12: u64,
+    #[serde(serialize_with = "hex")]
+    pub r13: u64,
+    #[serde(serialize_with = "hex")]
+    pub r14: u64,
+    #[serde(serialize_with = "hex")]
+    pub r15: u64,
+    #[serde(serialize_with = "hex")]
+    pub eflags: u32,
+}
+
+impl Registers {
+    /// The general purpose registers in the order they get printed
+    pub fn named(&self) -> [(&'static str, u64); 17] {
+        [
+            ("RAX", self.rax),
+            ("RBX", self.rbx),
+            ("RCX", self.rcx),
+            ("RDX", self.rdx),
+            ("RSI", self.rsi),
+            ("RDI", self.rdi),
+            ("RBP", self.rbp),
+            ("RSP", self.rsp),
+            ("RIP", self.rip),
+            ("R8 ", self.r8),
+            ("R9 ", self.r9),
+            ("R10", self.r10),
+            ("R11", self.r11),
+            ("R12", self.r12),
+            ("R13", self.r13),
+            ("R14", self.r14),
+            ("R15", self.r15),
+        ]
+    }
+}
+
+/// An address relative to the module it's in
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModuleOffset {
+    pub module: String,
+    #[serde(serialize_with = "hex_usize")]
+    pub base: usize,
+    #[serde(serialize_with = "hex_usize")]
+    pub offset: usize,
+    /// `GiveItem+0x1A`, when a symbol map covers the offset
+    pub symbol: Option<String>,
+}
+
+impl std::fmt::Display for ModuleOffset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{}!{}", self.module, symbol),
+            None => write!(f, "{}+0x{:X}", self.module, self.offset),
+        }
+    }
+}
+
+/// Finds the module containing `address` in `modules`
+pub fn locate(modules: &[LoadedModule], address: usize) -> Option<ModuleOffset> {
+    modules
+        .iter()
+        .find(|module| address >= module.base && address - module.base < module.size)
+        .map(|module| ModuleOffset {
+            module: module.name.clone(),
+            base: module.base,
+            offset: address - module.base,
+            symbol: None,
+        })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StackSlot {
+    /// Offset from RSP
+    #[serde(serialize_with = "hex_usize")]
+    pub offset: usize,
+    #[serde(serialize_with = "hex")]
+    pub value: u64,
+    /// Set when the value points into a loaded module, likely a return address
+    pub points_to: Option<ModuleOffset>,
+}
+
+/// Game and mod versions from the loader, if it reported them
+pub fn detected_versions() -> Option<serde_json::Value> {
+    #[cfg(feature = "dmc")]
+    {
+        crate::dmc::loader_parser::LOADER_STATUS
+            .get()
+            .and_then(|status| serde_json::to_value(status).ok())
+    }
+    #[cfg(not(feature = "dmc"))]
+    None
+}
+
+/// The last few log records, without waiting on the log buffer
+pub fn recent_log() -> Vec<LogEntry> {
+    try_query_logs(&LogQuery {
+        limit: Some(CRASH_CONTEXT_RECORDS),
+        ..Default::default()
+    })
+    .unwrap_or_default()
+}
+
+fn file_stamp(now: &chrono::DateTime<chrono::Local>) -> String {
+    now.format("%Y%m%d_%H%M%S_%3f").to_string()
+}
+
+fn write_report<T: Serialize>(dir: &Path, file_name: &str, report: &T) -> std::io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(file_name);
+    fs::write(&path, serde_json::to_string_pretty(report)?)?;
+    Ok(path)
+}
+
+/// One return address from the stack walk
+#[derive(Debug, Clone, Serialize)]
+pub struct StackFrame {
+    #[serde(serialize_with = "hex_usize")]
+    pub address: usize,
+    pub location: Option<ModuleOffset>,
+    /// The frame is in one of our own modules
+    pub ours: bool,
+}
+
+impl StackFrame {
+    pub fn new(address: usize, location: Option<ModuleOffset>, own_modules: &[String]) -> Self {
+        let ours = location.as_ref().is_some_and(|location| {
+            own_modules
+                .iter()
+                .any(|module| module.eq_ignore_ascii_case(&location.module))
+        });
+        Self {
+            address,
+            location,
+            ours,
+        }
+    }
+}
+
+impl std::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{}", location)?,
+            None => write!(f, "{:#X} (module unknown)", self.address)?,
+        }
+        if self.ours {
+            write!(f, " [randomizer]")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CrashReport {
+    pub timestamp: String,
+    #[serde(serialize_with = "hex")]
+    pub code: u32,
+    pub exception: String,
+    /// What the exception parameters say (I.e `write to 0x0 (null)`)
+    pub detail: Option<String>,
+    #[serde(serialize_with = "hex_usize")]
+    pub address: usize,
+    pub location: Option<ModuleOffset>,
+    pub cpp_exception_type: Option<String>,
+    pub registers: Registers,
+    /// Innermost first, starting at the faulting instruction
+    pub frames: Vec<StackFrame>,
+    pub stack: Vec<StackSlot>,
+    pub modules: Vec<LoadedModule>,
+    /// Game and mod versions, as detected by the loader
+    pub versions: Option<serde_json::Value>,
+    pub recent_log: Vec<LogEntry>,
+    #[serde(skip)]
+    file_stamp: String,
+}
+
+impl CrashReport {
+    pub fn new(
+        code: u32,
+        address: usize,
+        registers: Registers,
+        modules: Vec<LoadedModule>,
+    ) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            timestamp: now.to_rfc3339(),
+            code,
+            exception: exception_code_to_str(code).to_string(),
+            detail: None,
+            address,
+            location: locate(&modules, address),
+            cpp_exception_type: None,
+            registers,
+            frames: Vec::new(),
+            stack: Vec::new(),
+            modules,
+            versions: None,
+            recent_log: Vec::new(),
+            file_stamp: file_stamp(&now),
+        }
+    }
+
+    /// Fills in the stack window from the raw values starting at RSP
+    pub fn set_stack(&mut self, values: &[u64]) {
+        self.stack = values
+            .iter()
+            .enumerate()
+            .map(|(index, &value)| StackSlot {
+                offset: index * size_of::<u64>(),
+                value,
+                points_to: locate(&self.modules, value as usize),
+            })
+            .collect();
+    }
+
+    /// Fills in symbol names for every module+offset in the report, `lookup` takes the module name
+    /// and offset (I.e [`crate::symbols::symbolize`])
+    pub fn symbolize<F: Fn(&str, usize) -> Option<String>>(&mut self, lookup: F) {
+        let locations = self
+            .location
+            .iter_mut()
+            .chain(
+                self.frames
+                    .iter_mut()
+                    .filter_map(|frame| frame.location.as_mut()),
+            )
+            .chain(
+                self.stack
+                    .iter_mut()
+                    .filter_map(|slot| slot.points_to.as_mut()),
+            );
+        for location in locations {
+            location.symbol = lookup(&location.module, location.offset);
+        }
+    }
+
+    /// `crash_<timestamp>.json`
+    pub fn file_name(&self) -> String {
+        format!("crash_{}.json", self.file_stamp)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// What went wrong and where, for the log
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = vec![match &self.location {
+            Some(location) => format!(
+                "Exception {:#X} ({}) at {:#X} in {}",
+                self.code, self.exception, self.address, location
+            ),
+            None => format!(
+                "Exception {:#X} ({}) at {:#X} (module unknown)",
+                self.code, self.exception, self.address
+            ),
+        }];
+        if let Some(detail) = &self.detail {
+            lines.push(format!("Fault: {}", detail));
+        }
+        if let Some(type_name) = &self.cpp_exception_type {
+            lines.push(format!("C++ exception type: {}", type_name));
+        }
+        lines
+    }
+
+    /// The stack walk, one frame per line
+    pub fn frame_lines(&self) -> Vec<String> {
+        self.frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| format!("#{:<2} {}", index, frame))
+            .collect()
+    }
+
+    /// Register dump, three to a line
+    pub fn register_lines(&self) -> Vec<String> {
+        self.registers
+            .named()
+            .chunks(3)
+            .map(|registers| {
+                registers
+                    .iter()
+                    .map(|(name, value)| format!("{}={:#018x}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    /// Writes the report into `dir`, returning the path of the file
+    pub fn write_to(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        write_report(dir, &self.file_name(), self)
+    }
+}
+
+/// Same idea as [`CrashReport`], but for a Rust panic
+#[derive(Debug, Clone, Serialize)]
+pub struct PanicReport {
+    pub timestamp: String,
+    pub message: String,
+    /// `file:line:column` of the panic
+    pub location: Option<String>,
+    pub thread: String,
+    pub backtrace: Vec<String>,
+    pub versions: Option<serde_json::Value>,
+    pub recent_log: Vec<LogEntry>,
+    #[serde(skip)]
+    file_stamp: String,
+}
+
+impl PanicReport {
+    pub fn new(message: String, location: Option<String>, thread: String, backtrace: &str) -> Self {
+        let now = chrono::Local::now();
+        Self {
+            timestamp: now.to_rfc3339(),
+            message,
+            location,
+            thread,
+            backtrace: backtrace.lines().map(str::to_string).collect(),
+            versions: None,
+            recent_log: Vec::new(),
+            file_stamp: file_stamp(&now),
+        }
+    }
+
+    /// `crash_<timestamp>.json`, panics go next to the exception reports
+    pub fn file_name(&self) -> String {
+        format!("crash_{}.json", self.file_stamp)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// What went wrong and where, for the log
+    pub fn summary(&self) -> Vec<String> {
+        vec![format!(
+            "Panic on thread '{}' at {}: {}",
+            self.thread,
+            self.location.as_deref().unwrap_or("<unknown location>"),
+            self.message
+        )]
+    }
+
+    pub fn write_to(&self, dir: &Path) -> std::io::Result<PathBuf> {
+        write_report(dir, &self.file_name(), self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules() -> Vec<LoadedModule> {
+        vec![
+            LoadedModule {
+                name: "dmc3.exe".to_string(),
+                base: 0x1_4000_0000,
+                size: 0x10_0000,
+            },
+            LoadedModule {
+                name: "dinput8.dll".to_string(),
+                base: 0x7FF0_0000_0000,
+                size: 0x2_0000,
+            },
+        ]
+    }
+
+    fn report() -> CrashReport {
+        let registers = Registers {
+            rax: 0x1234,
+            rip: 0x1_4000_1A2B,
+            eflags: 0x246,
+            ..Registers::default()
+        };
+        CrashReport::new(0xC0000005, 0x1_4000_1A2B, registers, modules())
+    }
+
+    #[test]
+    fn locate_finds_the_containing_module() {
+        let modules = modules();
+        let location = locate(&modules, 0x7FF0_0000_1234).unwrap();
+        assert_eq!(location.module, "dinput8.dll");
+        assert_eq!(location.offset, 0x1234);
+        assert_eq!(location.to_string(), "dinput8.dll+0x1234");
+        // The end of an image is already outside of it
+        assert!(locate(&modules, 0x1_4010_0000).is_none());
+        assert!(locate(&modules, 0x10).is_none());
+    }
+
+    #[test]
+    fn summary_names_the_exception_and_module() {
+        let mut report = report();
+        report.detail = Some("write to 0x0 (null)".to_string());
+        report.cpp_exception_type = Some("std::runtime_error".to_string());
+        assert_eq!(
+            report.summary(),
+            vec![
+                "Exception 0xC0000005 (Access Violation) at 0x140001A2B in dmc3.exe+0x1A2B",
+                "Fault: write to 0x0 (null)",
+                "C++ exception type: std::runtime_error",
+            ]
+        );
+
+        let unknown = CrashReport::new(0xC0000005, 0x10, Registers::default(), modules());
+        assert_eq!(
+            unknown.summary(),
+            vec!["Exception 0xC0000005 (Access Violation) at 0x10 (module unknown)"]
+        );
+    }
+
+    #[test]
+    fn registers_are_printed_three_to_a_line() {
+        let lines = report().register_lines();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "RAX=0x0000000000001234 RBX=0x0000000000000000 RCX=0x0000000000000000"
+        );
+        assert!(lines[2].contains("RIP=0x0000000140001a2b"));
+        assert_eq!(lines[5], "R14=0x0000000000000000 R15=0x0000000000000000");
+    }
+
+    #[test]
+    fn frames_and_stack_point_into_modules() {
+        let mut report = report();
+        let own = vec!["DINPUT8.dll".to_string()];
+        report.frames = [0x1_4000_1A2B, 0x7FF0_0000_0100, 0x42]
+            .into_iter()
+            .map(|address| StackFrame::new(address, locate(&report.modules, address), &own))
+            .collect();
+        report.set_stack(&[0, 0x7FF0_0000_0200]);
+        report.symbolize(|module, offset| {
+            (module == "dinput8.dll" && offset == 0x100).then(|| "GiveItem+0x10".to_string())
+        });
+        assert_eq!(
+            report.frame_lines(),
+            vec![
+                "#0  dmc3.exe+0x1A2B",
+                "#1  dinput8.dll!GiveItem+0x10 [randomizer]",
+                "#2  0x42 (module unknown)",
+            ]
+        );
+        assert_eq!(report.stack[1].offset, 8);
+        assert_eq!(report.stack[1].points_to.as_ref().unwrap().offset, 0x200);
+        assert!(report.stack[0].points_to.is_none());
+    }
+
+    #[test]
+    fn serializes_addresses_as_hex() {
+        let mut report = report();
+        report.set_stack(&[0x1_4000_0010]);
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["code"], "0xC0000005");
+        assert_eq!(json["exception"], "Access Violation");
+        assert_eq!(json["address"], "0x140001A2B");
+        assert_eq!(json["location"]["module"], "dmc3.exe");
+        assert_eq!(json["location"]["offset"], "0x1A2B");
+        assert_eq!(json["registers"]["rax"], "0x1234");
+        assert_eq!(json["registers"]["eflags"], "0x246");
+        assert_eq!(json["stack"][0]["points_to"]["offset"], "0x10");
+        assert_eq!(json["modules"].as_array().unwrap().len(), 2);
+        assert!(json.get("file_stamp").is_none());
+        assert!(report.file_name().starts_with("crash_"));
+        assert!(report.file_name().ends_with(".json"));
+    }
+}
@@ -1,5 +1,6 @@
 use crate::BasicNothingFunc;
 use crate::dmc::dmc_helpers::DDMKHandler;
+use crate::panic_handler::guard_hook;
 use imgui_sys::{ImGuiCond, ImGuiWindowFlags, ImVec2, cty};
 use minhook::MinHook;
 use std::collections::HashSet;
@@ -13,6 +14,10 @@ pub static SETUP: AtomicBool = AtomicBool::new(false);
 static ORIG_TIMESTEP_FUNC: OnceLock<Option<BasicNothingFunc>> = OnceLock::new();
 
 pub fn run_common_ddmk_code() {
+    if crate::safe_mode::is_safe_mode() {
+        log::warn!("Safe mode, not hooking into DDMK");
+        return;
+    }
     if let Some(ddmk_info) = DDMK_INFO.get() {
         init_render_func(ddmk_info);
         init_timestep_func(ddmk_info);
@@ -38,7 +43,7 @@ fn init_timestep_func(ddmk_info: &DDMKHandler) {
 }
 
 unsafe extern "C" fn hooked_timestep() {
-    unsafe {
+    guard_hook("timestep", (), || unsafe {
         if !SETUP.load(Ordering::SeqCst)
             && let Some(ddmk_info) = DDMK_INFO.get()
         {
@@ -55,7 +60,7 @@ unsafe extern "C" fn hooked_timestep() {
                 timestep_func();
             }
         }
-    }
+    })
 }
 
 pub fn checkbox_text(item: &String, list: &HashSet<String>) -> String {
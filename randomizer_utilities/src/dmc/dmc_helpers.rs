@@ -1,6 +1,8 @@
+#[cfg(windows)]
 use crate::ui::dx11_types::PresentFn;
 use std::sync::LazyLock;
 
+#[cfg(windows)]
 #[derive(Debug)]
 pub struct OverlayHandler {
     pub create_device_addr: usize,
@@ -1,13 +1,18 @@
 use crate::dmc::versions::VersionInformation;
+use crate::symbols;
+use serde::Serialize;
 use std::fmt::{Display, Formatter};
 use std::sync::OnceLock;
+#[cfg(windows)]
 use windows::Win32::Foundation::FARPROC;
+#[cfg(windows)]
 use windows::Win32::System::LibraryLoader;
+#[cfg(windows)]
 use windows::core::PCSTR;
 
 pub static LOADER_STATUS: OnceLock<LoaderStatus> = OnceLock::new();
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 #[repr(C)]
 pub struct LoaderStatus {
     pub game_information: VersionInformation,
@@ -20,8 +25,10 @@ impl Display for LoaderStatus {
     }
 }
 
+#[cfg(windows)]
 type GetStatusFn = unsafe extern "C" fn() -> *const LoaderStatus;
 
+#[cfg(windows)]
 pub fn set_loader_status() {
     let loader_status = unsafe {
         let loader_hmodule =
@@ -33,7 +40,26 @@ pub fn set_loader_status() {
         (*std::mem::transmute::<FARPROC, GetStatusFn>(proc_addr)()).clone()
     };
     log::info!("Loader Status: {loader_status:?}");
+    load_symbol_maps(&loader_status);
     if LOADER_STATUS.set(loader_status).is_err() {
         log::error!("Failed to set global loader status");
     }
 }
+
+/// Loads the symbol maps from `archipelago/symbols` for the game and every mod, if there are any
+pub fn load_symbol_maps(status: &LoaderStatus) {
+    for version in std::iter::once(&status.game_information).chain(&status.mod_information) {
+        let path = symbols::symbols_dir().join(version.symbol_map_name());
+        if !path.exists() {
+            log::debug!(
+                "No symbol map for {} ({})",
+                version.description,
+                path.display()
+            );
+            continue;
+        }
+        if let Err(err) = symbols::load_symbol_map(version.get_file_name(), &path) {
+            log::warn!("Unable to load symbol map {}: {}", path.display(), err);
+        }
+    }
+}
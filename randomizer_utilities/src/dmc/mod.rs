@@ -1,4 +1,6 @@
+#[cfg(windows)]
 pub mod common_ddmk;
 pub mod dmc_helpers;
 pub mod loader_parser;
+pub mod patch_manifest;
 pub mod versions;
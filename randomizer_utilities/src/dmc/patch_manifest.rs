@@ -0,0 +1,529 @@
+//! Byte patches per game build, described in TOML instead of hard-coded offsets.
+//!
+//! Each `[[build]]` is keyed by game and build hash (the same hash as in [`VersionInformation`],
+//! as a decimal or `0x` hex string since it doesn't fit in a TOML integer). Bytes are hex, spaces
+//! between them are optional. `module` defaults to the module of the build, and `name` to
+//! `module+0xOFFSET`.
+//! ```toml
+//! [[build]]
+//! game = "DMC3"
+//! hash = "0x5E2F0A1B2C3D4E5F"
+//! description = "Latest DMC3"
+//!
+//! [[build.patch]]
+//! name = "skip_intro"
+//! module = "dmc3.exe"
+//! offset = 0x1A2B30
+//! expected = "74 05"
+//! replacement = "EB 05"
+//! description = "Jump over the intro movie"
+//! ```
+use crate::dmc::versions::{Game, VersionInformation};
+use crate::patches::{Patch, PatchManager};
+use crate::paths;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct PatchManifestError {
+    /// Which build and patch the error is in, if any
+    pub context: String,
+    pub message: String,
+}
+
+impl PatchManifestError {
+    fn new(context: &str, message: impl Into<String>) -> Self {
+        Self {
+            context: context.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for PatchManifestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.context.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.context, self.message)
+        }
+    }
+}
+
+impl Error for PatchManifestError {}
+
+// What's in the file, before validation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    #[serde(default)]
+    build: Vec<RawBuild>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawBuild {
+    game: Game,
+    hash: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    patch: Vec<RawPatch>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawPatch {
+    name: Option<String>,
+    module: Option<String>,
+    offset: usize,
+    expected: String,
+    replacement: String,
+    #[serde(default)]
+    description: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestPatch {
+    pub name: String,
+    /// None for the module of the build
+    pub module: Option<String>,
+    /// Offset from the module base
+    pub offset: usize,
+    pub expected: Vec<u8>,
+    pub replacement: Vec<u8>,
+    pub description: String,
+}
+
+/// The patches for one build of one game
+#[derive(Debug, Clone)]
+pub struct BuildPatches {
+    pub game: Game,
+    pub hash: u64,
+    pub description: String,
+    pub patches: Vec<ManifestPatch>,
+}
+
+impl BuildPatches {
+    /// Owner used for these patches in the [`PatchManager`]
+    pub fn owner(&self) -> String {
+        format!("manifest {} {:016x}", self.game, self.hash)
+    }
+
+    /// Makes sure no two patches touch the same bytes, `default_module` being the module used
+    /// for patches that don't name one
+    pub fn check_overlaps(&self, default_module: &str) -> Result<(), PatchManifestError> {
+        for (index, patch) in self.patches.iter().enumerate() {
+            let module = patch.module.as_deref().unwrap_or(default_module);
+            for other in &self.patches[..index] {
+                if other
+                    .module
+                    .as_deref()
+                    .unwrap_or(default_module)
+                    .eq_ignore_ascii_case(module)
+                    && patch.offset < other.offset + other.replacement.len()
+                    && other.offset < patch.offset + patch.replacement.len()
+                {
+                    return Err(PatchManifestError::new(
+                        &format!("{} {:016x} {}", self.game, self.hash, patch.name),
+                        format!("overlaps {}", other.name),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns the entries into patches at absolute addresses, `module_base` returns the base of a
+    /// module by name (I.e [`crate::get_base_address`])
+    pub fn resolve<F: Fn(&str) -> Option<usize>>(
+        &self,
+        default_module: &str,
+        module_base: F,
+    ) -> Result<Vec<Patch>, PatchManifestError> {
+        self.check_overlaps(default_module)?;
+        let owner = self.owner();
+        self.patches
+            .iter()
+            .map(|patch| {
+                let module = patch.module.as_deref().unwrap_or(default_module);
+                let base = module_base(module).ok_or_else(|| {
+                    PatchManifestError::new(&patch.name, format!("module {} is not loaded", module))
+                })?;
+                Ok(Patch::new(
+                    &patch.name,
+                    &owner,
+                    base + patch.offset,
+                    &patch.expected,
+                    &patch.replacement,
+                ))
+            })
+            .collect()
+    }
+
+    /// Registers every patch with `manager` without applying them, or none of them if any fails
+    pub fn register<F: Fn(&str) -> Option<usize>>(
+        &self,
+        default_module: &str,
+        module_base: F,
+        manager: &mut PatchManager,
+    ) -> Result<usize, Box<dyn Error>> {
+        let patches = self.resolve(default_module, module_base)?;
+        let mut registered: Vec<String> = Vec::with_capacity(patches.len());
+        for patch in patches {
+            let name = patch.name.clone();
+            if let Err(err) = manager.register(patch) {
+                for name in registered.iter().rev() {
+                    if let Err(err) = manager.unregister(name) {
+                        log::error!("Unable to roll back manifest patch {}: {}", name, err);
+                    }
+                }
+                return Err(err.into());
+            }
+            registered.push(name);
+        }
+        Ok(registered.len())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PatchManifest {
+    pub builds: Vec<BuildPatches>,
+}
+
+fn parse_hash(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// `"74 05"` or `"7405"` into bytes
+fn parse_bytes(value: &str) -> Option<Vec<u8>> {
+    let digits: String = value.split_whitespace().collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(digits.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+impl PatchManifest {
+    pub fn parse(text: &str) -> Result<Self, PatchManifestError> {
+        let raw: RawManifest =
+            toml::from_str(text).map_err(|err| PatchManifestError::new("", err.to_string()))?;
+        let mut builds: Vec<BuildPatches> = Vec::with_capacity(raw.build.len());
+        for raw_build in raw.build {
+            let build_context = format!("{} {}", raw_build.game, raw_build.hash);
+            let hash = parse_hash(&raw_build.hash)
+                .ok_or_else(|| PatchManifestError::new(&build_context, "invalid build hash"))?;
+            if builds
+                .iter()
+                .any(|build| build.game == raw_build.game && build.hash == hash)
+            {
+                return Err(PatchManifestError::new(
+                    &build_context,
+                    "build is listed more than once",
+                ));
+            }
+            let mut patches: Vec<ManifestPatch> = Vec::with_capacity(raw_build.patch.len());
+            for raw_patch in raw_build.patch {
+                let module = raw_patch.module;
+                let name = raw_patch.name.unwrap_or_else(|| match &module {
+                    Some(module) => format!("{}+0x{:X}", module, raw_patch.offset),
+                    None => format!("{}+0x{:X}", raw_build.game, raw_patch.offset),
+                });
+                let context = format!("{} {}", build_context, name);
+                let expected = parse_bytes(&raw_patch.expected)
+                    .ok_or_else(|| PatchManifestError::new(&context, "invalid expected bytes"))?;
+                let replacement = parse_bytes(&raw_patch.replacement).ok_or_else(|| {
+                    PatchManifestError::new(&context, "invalid replacement bytes")
+                })?;
+                if expected.len() != replacement.len() {
+                    return Err(PatchManifestError::new(
+                        &context,
+                        "expected and replacement bytes have different lengths",
+                    ));
+                }
+                let patch = ManifestPatch {
+                    name,
+                    module,
+                    offset: raw_patch.offset,
+                    expected,
+                    replacement,
+                    description: raw_patch.description,
+                };
+                if patches.iter().any(|other| other.name == patch.name) {
+                    return Err(PatchManifestError::new(&context, "duplicate patch name"));
+                }
+                patches.push(patch);
+            }
+            builds.push(BuildPatches {
+                game: raw_build.game,
+                hash,
+                description: raw_build.description,
+                patches,
+            });
+        }
+        Ok(Self { builds })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::parse(&fs::read_to_string(path)?)?)
+    }
+
+    /// The patch set for the running build, if the manifest has one
+    pub fn for_version(&self, version: &VersionInformation) -> Option<&BuildPatches> {
+        self.builds
+            .iter()
+            .find(|build| build.game == version.game_type && build.hash == version.hash())
+    }
+}
+
+/// `<data root>/archipelago/patches.toml`
+pub fn manifest_path() -> PathBuf {
+    paths::archipelago_dir().join("patches.toml")
+}
+
+/// Registers the patches `manifest` has for `version` with `manager`, without applying them
+///
+/// Returns how many were registered, 0 if the manifest doesn't know the build. Nothing is
+/// registered if any of them can't be.
+pub fn register_manifest_patches(
+    manifest: &PatchManifest,
+    version: &VersionInformation,
+    manager: &mut PatchManager,
+) -> Result<usize, Box<dyn Error>> {
+    let Some(build) = manifest.for_version(version) else {
+        log::info!(
+            "No manifest patches for {} ({:016x})",
+            version.description,
+            version.hash()
+        );
+        return Ok(0);
+    };
+    let count = build.register(
+        version.get_file_name(),
+        |module| Some(crate::get_base_address(module)).filter(|base| *base != 0),
+        manager,
+    )?;
+    log::info!(
+        "Registered {} manifest patches for {} ({})",
+        count,
+        version.description,
+        build.description
+    );
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MockMemory;
+    use std::sync::Arc;
+
+    const MANIFEST: &str = r#"
+        [[build]]
+        game = "DMC3"
+        hash = "0x5E2F0A1B2C3D4E5F"
+        description = "Test DMC3"
+
+        [[build.patch]]
+        name = "skip_intro"
+        offset = 0x1A2B30
+        expected = "74 05"
+        replacement = "EB 05"
+
+        [[build.patch]]
+        module = "Crimson.dll"
+        offset = 0x20
+        expected = "9090"
+        replacement = "CC CC"
+
+        [[build]]
+        game = "DMC1"
+        hash = "1234"
+    "#;
+
+    const BASE: usize = 0x1_4000_0000;
+
+    fn module_base(module: &str) -> Option<usize> {
+        match module.to_ascii_lowercase().as_str() {
+            "dmc3.exe" => Some(BASE),
+            "crimson.dll" => Some(0x7FF0_0000_0000),
+            _ => None,
+        }
+    }
+
+    fn build_with(patches: &str) -> BuildPatches {
+        let manifest = PatchManifest::parse(&format!(
+            "[[build]]\ngame = \"DMC3\"\nhash = \"0x10\"\n{}",
+            patches
+        ))
+        .unwrap();
+        manifest.builds[0].clone()
+    }
+
+    #[test]
+    fn parses_a_valid_manifest() {
+        let manifest = PatchManifest::parse(MANIFEST).unwrap();
+        assert_eq!(manifest.builds.len(), 2);
+        let build = &manifest.builds[0];
+        assert_eq!(build.game, Game::DMC3);
+        assert_eq!(build.hash, 0x5E2F0A1B2C3D4E5F);
+        assert_eq!(build.description, "Test DMC3");
+        assert_eq!(
+            build.patches[0],
+            ManifestPatch {
+                name: "skip_intro".to_string(),
+                module: None,
+                offset: 0x1A2B30,
+                expected: vec![0x74, 0x05],
+                replacement: vec![0xEB, 0x05],
+                description: String::new(),
+            }
+        );
+        assert_eq!(build.patches[1].name, "Crimson.dll+0x20");
+        assert_eq!(manifest.builds[1].hash, 1234);
+        assert!(manifest.builds[1].patches.is_empty());
+
+        let patches = build.resolve("dmc3.exe", module_base).unwrap();
+        assert_eq!(patches[0].address, BASE + 0x1A2B30);
+        assert_eq!(patches[0].owner, "manifest DMC3 5e2f0a1b2c3d4e5f");
+        assert_eq!(patches[1].address, 0x7FF0_0000_0020);
+    }
+
+    #[test]
+    fn rejects_duplicates() {
+        let duplicate_patch = PatchManifest::parse(
+            r#"
+            [[build]]
+            game = "DMC3"
+            hash = "0x10"
+            [[build.patch]]
+            name = "same"
+            offset = 0x10
+            expected = "00"
+            replacement = "01"
+            [[build.patch]]
+            name = "same"
+            offset = 0x40
+            expected = "00"
+            replacement = "01"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(duplicate_patch.message, "duplicate patch name");
+
+        let duplicate_build = PatchManifest::parse(
+            r#"
+            [[build]]
+            game = "DMC3"
+            hash = "0x10"
+            [[build]]
+            game = "DMC3"
+            hash = "16"
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(duplicate_build.message, "build is listed more than once");
+    }
+
+    #[test]
+    fn rejects_overlapping_patches_in_the_resolved_module() {
+        let build = build_with(
+            r#"
+            [[build.patch]]
+            name = "first"
+            offset = 0x10
+            expected = "00 00 00 00"
+            replacement = "01 01 01 01"
+            [[build.patch]]
+            name = "second"
+            module = "DMC3.EXE"
+            offset = 0x12
+            expected = "00"
+            replacement = "01"
+            "#,
+        );
+        let err = build.check_overlaps("dmc3.exe").unwrap_err();
+        assert_eq!(err.message, "overlaps first");
+        assert!(build.resolve("dmc3.exe", module_base).is_err());
+        // Different modules once the default is something else
+        assert!(build.check_overlaps("Crimson.dll").is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_hashes_and_bytes() {
+        let bad_hash = PatchManifest::parse("[[build]]\ngame = \"DMC3\"\nhash = \"0xNOPE\"\n");
+        assert_eq!(bad_hash.unwrap_err().message, "invalid build hash");
+        let mismatch = PatchManifest::parse(
+            "[[build]]\ngame = \"DMC3\"\nhash = \"1\"\n[[build.patch]]\noffset = 1\nexpected = \"00 00\"\nreplacement = \"01\"\n",
+        );
+        assert_eq!(
+            mismatch.unwrap_err().message,
+            "expected and replacement bytes have different lengths"
+        );
+        assert!(PatchManifest::parse("[[build]]\ngame = \"DMC9\"\nhash = \"1\"\n").is_err());
+    }
+
+    #[test]
+    fn only_matches_the_exact_build() {
+        let version = Game::DMC3.get_information()[0];
+        let manifest = PatchManifest::parse(&format!(
+            "[[build]]\ngame = \"DMC3\"\nhash = \"{:#x}\"\n",
+            version.hash()
+        ))
+        .unwrap();
+        assert!(manifest.for_version(&version).is_some());
+        let wrong_hash = PatchManifest::parse(&format!(
+            "[[build]]\ngame = \"DMC3\"\nhash = \"{:#x}\"\n",
+            version.hash().wrapping_add(1)
+        ))
+        .unwrap();
+        assert!(wrong_hash.for_version(&version).is_none());
+    }
+
+    #[test]
+    fn registration_is_all_or_nothing() {
+        let mut manager = PatchManager::with_accessor(Arc::new(MockMemory::new()));
+        manager
+            .register(Patch::new("existing", "other", BASE + 0x40, &[0], &[1]))
+            .unwrap();
+        let build = build_with(
+            r#"
+            [[build.patch]]
+            name = "fine"
+            offset = 0x10
+            expected = "00"
+            replacement = "01"
+            [[build.patch]]
+            name = "clashes"
+            offset = 0x40
+            expected = "00"
+            replacement = "01"
+            "#,
+        );
+        assert!(
+            build
+                .register("dmc3.exe", module_base, &mut manager)
+                .is_err()
+        );
+        let names: Vec<String> = manager.status().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, ["existing"]);
+
+        let mut empty = PatchManager::with_accessor(Arc::new(MockMemory::new()));
+        assert_eq!(
+            build.register("dmc3.exe", module_base, &mut empty).unwrap(),
+            2
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::dmc::versions::Game::Unknown;
+use serde::{Deserialize, Serialize};
 use std::env::current_exe;
 use std::fmt::{Display, Formatter};
 use std::fs;
@@ -7,7 +8,7 @@ use std::sync::LazyLock;
 use xxhash_rust::const_xxh3::xxh3_64;
 
 // Records of various DMCHDC hashes and mods (Not complete, need GOG)
-#[derive(Debug, Clone, Copy, strum_macros::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, Serialize, Deserialize)]
 pub enum Game {
     // HD Collection
     DMCLauncher,
@@ -17,7 +18,7 @@ pub enum Game {
 
     Unknown,
 }
-#[derive(Debug, Clone, Copy, strum_macros::Display)]
+#[derive(Debug, Clone, Copy, strum_macros::Display, Serialize)]
 pub enum Mod {
     // Mods (Probably not going to add every DDMK/Crimson Version, only from the time of writing and onwards)
     Eva,
@@ -26,7 +27,7 @@ pub enum Mod {
     Crimson,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 #[repr(C)]
 pub struct VersionInformation {
     hash: u64,
@@ -36,6 +37,28 @@ pub struct VersionInformation {
     pub mod_type: Option<Mod>,
 }
 
+impl VersionInformation {
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// File name of the module this version describes (I.e "dmc3.exe" or "Crimson.dll")
+    pub fn get_file_name(&self) -> &str {
+        match &self.mod_type {
+            Some(game_mod) => game_mod.get_file_name(),
+            None => self.game_type.get_file_name(),
+        }
+    }
+
+    /// Name of the symbol map for this exact build (I.e "DMC3_5e2f....txt")
+    pub fn symbol_map_name(&self) -> String {
+        match self.mod_type {
+            Some(game_mod) => format!("{}_{:016x}.txt", game_mod, self.hash),
+            None => format!("{}_{:016x}.txt", self.game_type, self.hash),
+        }
+    }
+}
+
 impl Display for VersionInformation {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
@@ -381,3 +404,57 @@ pub fn is_file_valid(file_path: &str, expected_hash: u64) -> Result<(), std::io:
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_hash_is_checked() {
+        let path = crate::paths::test_data_root().join("versions_hash_test.bin");
+        let contents = b"not actually dmc3.exe";
+        fs::write(&path, contents).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(is_file_valid(path, xxh3_64(contents)).is_ok());
+        assert_eq!(
+            is_file_valid(path, xxh3_64(b"something else"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            is_file_valid(&format!("{path}.missing"), 0)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn catalogued_hashes_are_unique() {
+        let mut hashes: Vec<u64> = [Game::DMCLauncher, Game::DMC1, Game::DMC2, Game::DMC3]
+            .iter()
+            .flat_map(|game| game.get_information())
+            .chain(
+                [Mod::Eva, Mod::Lucia, Mod::Mary, Mod::Crimson]
+                    .iter()
+                    .flat_map(|game_mod| game_mod.get_information()),
+            )
+            .map(VersionInformation::hash)
+            .collect();
+        let count = hashes.len();
+        hashes.sort_unstable();
+        hashes.dedup();
+        assert_eq!(hashes.len(), count);
+    }
+
+    #[test]
+    fn symbol_maps_are_named_after_the_build() {
+        let version = Game::DMC3.get_information()[0];
+        assert_eq!(
+            version.symbol_map_name(),
+            format!("DMC3_{:016x}.txt", version.hash())
+        );
+        assert_eq!(version.get_file_name(), "dmc3.exe");
+    }
+}
@@ -0,0 +1,270 @@
+//! Decides what the vectored exception handler does with an exception.
+//!
+//! The handler sees every exception first-chance, before the game had a chance to catch it, so
+//! most of what it sees (C++ exceptions, debugger signals) never takes the process down. All it
+//! logs is a short rate limited line, the full crash report comes from the unhandled exception
+//! filter once nothing caught the exception.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const CPP_EXCEPTION: u32 = 0xE06D7363;
+const CLR_EXCEPTION: u32 = 0xE0434352;
+const BREAKPOINT: u32 = 0x80000003;
+const SINGLE_STEP: u32 = 0x80000004;
+const WX86_BREAKPOINT: u32 = 0x4000001F;
+const DBG_CONTROL_C: u32 = 0x40010005;
+const DBG_PRINTEXCEPTION_C: u32 = 0x40010006;
+const DBG_CONTROL_BREAK: u32 = 0x40010008;
+const DBG_PRINTEXCEPTION_WIDE_C: u32 = 0x4001000A;
+const SET_THREAD_NAME: u32 = 0x406D1388;
+
+/// Bit 29 of an exception code marks it as application defined
+const CUSTOMER_BIT: u32 = 1 << 29;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+pub enum ExceptionClass {
+    /// Takes the process down unless something catches it, logged as a warning
+    Fatal,
+    /// Usually caught by whoever raised it (I.e C++ exceptions)
+    LikelyHandled,
+    /// Debugger signals and the like, not worth a line in the log
+    Ignored,
+}
+
+/// Classifies an exception from its code, `noncontinuable` being `EXCEPTION_NONCONTINUABLE`
+pub fn classify_exception(code: u32, noncontinuable: bool) -> ExceptionClass {
+    match code {
+        DBG_CONTROL_C
+        | DBG_PRINTEXCEPTION_C
+        | DBG_CONTROL_BREAK
+        | DBG_PRINTEXCEPTION_WIDE_C
+        | SET_THREAD_NAME
+        | SINGLE_STEP
+        | WX86_BREAKPOINT => ExceptionClass::Ignored,
+        _ if noncontinuable => ExceptionClass::Fatal,
+        CPP_EXCEPTION | CLR_EXCEPTION | BREAKPOINT => ExceptionClass::LikelyHandled,
+        _ if code & CUSTOMER_BIT != 0 => ExceptionClass::LikelyHandled,
+        // The top two bits are the severity: success, informational, warning, error
+        _ => match code >> 30 {
+            0 | 1 => ExceptionClass::Ignored,
+            2 => ExceptionClass::LikelyHandled,
+            _ => ExceptionClass::Fatal,
+        },
+    }
+}
+
+/// What to do with one occurrence of an exception
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// First time this code was seen at this address
+    First,
+    /// Seen before, `suppressed` is how many were dropped since the last one that got through
+    Repeat { total: u64, suppressed: u64 },
+    /// Over the rate limit, nothing should be logged
+    Suppress,
+}
+
+#[derive(Debug)]
+struct Seen {
+    total: u64,
+    suppressed: u64,
+    window_start: Instant,
+    in_window: u32,
+    last_seen: Instant,
+}
+
+/// How many code and address pairs are remembered before the least recently seen is dropped
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Dedupes exceptions by code and address, letting at most `per_window` of each through per
+/// `window`
+#[derive(Debug)]
+pub struct ExceptionFilter {
+    seen: HashMap<(u32, usize), Seen>,
+    per_window: u32,
+    window: Duration,
+    capacity: usize,
+}
+
+impl ExceptionFilter {
+    pub fn new(per_window: u32, window: Duration) -> Self {
+        Self::with_capacity(per_window, window, DEFAULT_CAPACITY)
+    }
+
+    /// Remembers at most `capacity` code and address pairs (I.e a game throwing from all over the
+    /// place), a pair that was dropped counts as new the next time it shows up
+    pub fn with_capacity(per_window: u32, window: Duration, capacity: usize) -> Self {
+        Self {
+            seen: HashMap::with_capacity(capacity.max(1)),
+            per_window,
+            window,
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn check(&mut self, code: u32, address: usize, now: Instant) -> Decision {
+        let Some(seen) = self.seen.get_mut(&(code, address)) else {
+            if self.seen.len() >= self.capacity
+                && let Some(oldest) = self
+                    .seen
+                    .iter()
+                    .min_by_key(|(_, seen)| seen.last_seen)
+                    .map(|(key, _)| *key)
+            {
+                self.seen.remove(&oldest);
+            }
+            self.seen.insert(
+                (code, address),
+                Seen {
+                    total: 1,
+                    suppressed: 0,
+                    window_start: now,
+                    in_window: 1,
+                    last_seen: now,
+                },
+            );
+            return Decision::First;
+        };
+        seen.total += 1;
+        seen.last_seen = now;
+        if now.duration_since(seen.window_start) >= self.window {
+            seen.window_start = now;
+            seen.in_window = 0;
+        }
+        if seen.in_window >= self.per_window {
+            seen.suppressed += 1;
+            return Decision::Suppress;
+        }
+        seen.in_window += 1;
+        let suppressed = std::mem::take(&mut seen.suppressed);
+        Decision::Repeat {
+            total: seen.total,
+            suppressed,
+        }
+    }
+}
+
+impl Default for ExceptionFilter {
+    fn default() -> Self {
+        Self::new(3, Duration::from_secs(10))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCESS_VIOLATION: u32 = 0xC0000005;
+    const STACK_OVERFLOW: u32 = 0xC00000FD;
+
+    #[test]
+    fn classifies_fatal_and_benign_codes() {
+        assert_eq!(
+            classify_exception(ACCESS_VIOLATION, false),
+            ExceptionClass::Fatal
+        );
+        assert_eq!(
+            classify_exception(STACK_OVERFLOW, false),
+            ExceptionClass::Fatal
+        );
+        assert_eq!(
+            classify_exception(CPP_EXCEPTION, false),
+            ExceptionClass::LikelyHandled
+        );
+        assert_eq!(
+            classify_exception(CLR_EXCEPTION, false),
+            ExceptionClass::LikelyHandled
+        );
+        assert_eq!(
+            classify_exception(BREAKPOINT, false),
+            ExceptionClass::LikelyHandled
+        );
+        assert_eq!(
+            classify_exception(0xE0000001, false),
+            ExceptionClass::LikelyHandled
+        );
+        // Warning severity (I.e a guard page being hit)
+        assert_eq!(
+            classify_exception(0x80000001, false),
+            ExceptionClass::LikelyHandled
+        );
+        for code in [
+            DBG_PRINTEXCEPTION_C,
+            SET_THREAD_NAME,
+            SINGLE_STEP,
+            0x40000015,
+        ] {
+            assert_eq!(
+                classify_exception(code, false),
+                ExceptionClass::Ignored,
+                "{code:#X}"
+            );
+        }
+    }
+
+    #[test]
+    fn noncontinuable_exceptions_are_fatal() {
+        assert_eq!(
+            classify_exception(CPP_EXCEPTION, true),
+            ExceptionClass::Fatal
+        );
+        assert_eq!(classify_exception(0x40000015, true), ExceptionClass::Fatal);
+        // Debugger signals stay ignored either way
+        assert_eq!(
+            classify_exception(DBG_PRINTEXCEPTION_C, true),
+            ExceptionClass::Ignored
+        );
+    }
+
+    #[test]
+    fn repeats_are_limited_per_window() {
+        let mut filter = ExceptionFilter::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        assert_eq!(filter.check(CPP_EXCEPTION, 0x1000, start), Decision::First);
+        assert_eq!(
+            filter.check(CPP_EXCEPTION, 0x1000, start),
+            Decision::Repeat {
+                total: 2,
+                suppressed: 0
+            }
+        );
+        assert_eq!(
+            filter.check(CPP_EXCEPTION, 0x1000, start),
+            Decision::Suppress
+        );
+        assert_eq!(
+            filter.check(CPP_EXCEPTION, 0x1000, start),
+            Decision::Suppress
+        );
+        // A different address is counted on its own
+        assert_eq!(filter.check(CPP_EXCEPTION, 0x2000, start), Decision::First);
+
+        let later = start + Duration::from_secs(10);
+        assert_eq!(
+            filter.check(CPP_EXCEPTION, 0x1000, later),
+            Decision::Repeat {
+                total: 5,
+                suppressed: 2
+            }
+        );
+    }
+
+    #[test]
+    fn least_recently_seen_pairs_are_dropped() {
+        let mut filter = ExceptionFilter::with_capacity(1, Duration::from_secs(10), 2);
+        let start = Instant::now();
+        filter.check(CPP_EXCEPTION, 0x1000, start);
+        filter.check(CPP_EXCEPTION, 0x2000, start + Duration::from_millis(1));
+        filter.check(CPP_EXCEPTION, 0x1000, start + Duration::from_millis(2));
+        filter.check(CPP_EXCEPTION, 0x3000, start + Duration::from_millis(3));
+        assert_eq!(filter.seen.len(), 2);
+        assert_eq!(
+            filter.check(CPP_EXCEPTION, 0x2000, start + Duration::from_millis(4)),
+            Decision::First
+        );
+        assert_ne!(
+            filter.check(CPP_EXCEPTION, 0x3000, start + Duration::from_millis(5)),
+            Decision::First
+        );
+    }
+}
@@ -1,165 +1,619 @@
+#[cfg(windows)]
+use crate::crash_callbacks::run_crash_callbacks;
+use crate::crash_callbacks::{DEFAULT_BUDGET, register_crash_callback};
+#[cfg(windows)]
+use crate::crash_report::{
+    CrashReport, MAX_FRAMES, ModuleOffset, Registers, STACK_WINDOW, StackFrame, detected_versions,
+    locate, own_modules, recent_log,
+};
+#[cfg(windows)]
+use crate::exception_filter::{
+    CPP_EXCEPTION, Decision, ExceptionClass, ExceptionFilter, classify_exception,
+};
+#[cfg(windows)]
+use crate::paths;
+#[cfg(windows)]
+use crate::platform::{LoadedModule, loaded_modules, module_from_address, read_memory};
+#[cfg(windows)]
+use std::cell::Cell;
 use std::sync::OnceLock;
-use std::{ffi::c_void, path::Path};
-use windows::Win32::Foundation::HMODULE;
-use windows::Win32::System::Diagnostics::Debug::{AddVectoredExceptionHandler, EXCEPTION_POINTERS};
-use windows::Win32::System::LibraryLoader::{
-    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GetModuleFileNameW, GetModuleHandleExW,
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(windows)]
+use std::sync::{LazyLock, Mutex};
+#[cfg(windows)]
+use std::time::Instant;
+#[cfg(windows)]
+use windows::Win32::System::Diagnostics::Debug::{
+    AddVectoredExceptionHandler, CONTEXT, EXCEPTION_POINTERS, EXCEPTION_RECORD,
+    LPTOP_LEVEL_EXCEPTION_FILTER, RtlLookupFunctionEntry, RtlVirtualUnwind,
+    SetUnhandledExceptionFilter, UNW_FLAG_NHANDLER,
 };
-use windows::core::PCWSTR;
 
-// There's probably more to add, but figured this would be good to start
-fn exception_code_to_str(code: u32) -> &'static str {
+pub fn exception_code_to_str(code: u32) -> &'static str {
     match code {
-        0xC0000005 => "Access Violation",
+        // Warnings
+        0x80000001 => "Guard Page Violation",
+        0x80000002 => "Datatype Misalignment",
         0x80000003 => "Breakpoint",
-        0xC0000094 => "Integer Divide by Zero",
+        0x80000004 => "Single Step",
+        0x80000026 => "Long Jump",
+        // Errors
+        0xC0000005 => "Access Violation",
+        0xC0000006 => "In-Page Error",
+        0xC0000008 => "Invalid Handle",
+        0xC000000D => "Invalid Parameter",
+        0xC0000017 => "Out of Memory",
         0xC000001D => "Illegal Instruction",
+        0xC0000025 => "Noncontinuable Exception",
+        0xC0000026 => "Invalid Disposition",
+        0xC000008C => "Array Bounds Exceeded",
+        0xC000008D => "Floating-Point Denormal Operand",
         0xC000008E => "Floating-Point Divide by Zero",
+        0xC000008F => "Floating-Point Inexact Result",
+        0xC0000090 => "Floating-Point Invalid Operation",
+        0xC0000091 => "Floating-Point Overflow",
+        0xC0000092 => "Floating-Point Stack Check",
+        0xC0000093 => "Floating-Point Underflow",
+        0xC0000094 => "Integer Divide by Zero",
+        0xC0000095 => "Integer Overflow",
         0xC0000096 => "Privileged Instruction",
         0xC00000FD => "Stack Overflow",
+        0xC0000135 => "DLL Not Found",
+        0xC0000139 => "Entry Point Not Found",
+        0xC000013A => "Control-C Exit",
+        0xC0000142 => "DLL Initialization Failed",
+        0xC0000194 => "Possible Deadlock",
+        0xC00002B4 => "Multiple Floating-Point Faults",
+        0xC00002B5 => "Multiple Floating-Point Traps",
         0xC0000374 => "Heap Corruption",
+        0xC0000409 => "Stack Buffer Overrun",
+        0xC0000417 => "Invalid C Runtime Parameter",
+        0xC000041D => "Fatal User Callback Exception",
+        0xC0000602 => "Fail Fast Exception",
+        // Debugger signals
+        0x4000001F => "WOW64 Breakpoint",
+        0x40010005 => "Control-C",
+        0x40010006 => "Debug Print",
+        0x40010008 => "Control-Break",
+        0x4001000A => "Debug Print (Wide)",
+        0x406D1388 => "Set Thread Name",
+        // Language runtimes
         0xE06D7363 => "C++ Exception",
+        0xE0434352 => ".NET Exception",
         _ => "Unknown Exception",
     }
 }
 
-/// Resolve address → (module base, filename)
-unsafe fn module_from_address(addr: *const c_void) -> Option<(HMODULE, String)> {
-    let mut hmod = HMODULE::default();
-    unsafe {
-        if GetModuleHandleExW(
-            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
-            PCWSTR::from_raw(addr as *const u16),
-            &mut hmod,
-        )
-        .is_err()
-        {
-            return None;
+/// `0x0 (null)`, with small addresses called out as near null (I.e a field of a null pointer)
+fn describe_address(address: usize) -> String {
+    match address {
+        0 => "0x0 (null)".to_string(),
+        1..0x10000 => format!("{:#X} (near null)", address),
+        _ => format!("{:#X}", address),
+    }
+}
+
+/// `__fastfail` codes from winnt.h, passed as the first parameter of a stack buffer overrun
+fn fail_fast_code_to_str(code: usize) -> Option<&'static str> {
+    Some(match code {
+        0 => "legacy /GS violation",
+        1 => "v-table guard check failure",
+        2 => "stack cookie check failure",
+        3 => "corrupt list entry",
+        4 => "incorrect stack",
+        5 => "invalid argument",
+        6 => "/GS cookie initialization failure",
+        7 => "fatal app exit",
+        8 => "range check failure",
+        9 => "unsafe registry access",
+        10 => "control flow guard check failure",
+        11 => "guard write check failure",
+        12 => "invalid fiber switch",
+        13 => "invalid set of context",
+        14 => "invalid reference count",
+        18 => "invalid jump buffer",
+        19 => "mutable read-only data modified",
+        20 => "certification failure",
+        21 => "invalid exception chain",
+        22 => "crypto library",
+        23 => "invalid call in dll callout",
+        24 => "invalid image base",
+        25 => "delay load protection failure",
+        26 => "unsafe extension call",
+        27 => "deprecated service invoked",
+        28 => "invalid buffer access",
+        29 => "invalid balanced tree",
+        30 => "invalid next thread",
+        31 => "control flow guard check suppressed",
+        32 => "APCs disabled",
+        33 => "invalid idle state",
+        34 => "mutable read-only data protection failure",
+        35 => "unexpected heap exception",
+        36 => "invalid lock state",
+        37 => "control flow guard jump table failure",
+        38 => "invalid longjump target",
+        39 => "invalid dispatch context",
+        40 => "invalid thread",
+        41 => "invalid syscall number",
+        42 => "invalid file operation",
+        43 => "LPAC access denied",
+        44 => "shadow stack failure",
+        45 => "loader continuity failure",
+        46 => "control flow guard export suppression failure",
+        47 => "invalid control stack",
+        48 => "set context denied",
+        49 => "invalid IAT",
+        50 => "heap metadata corruption",
+        51 => "payload restriction violation",
+        52 => "low label access denied",
+        53 => "enclave call failure",
+        54 => "unhandled LSS exception",
+        55 => "adminless access denied",
+        56 => "unexpected call",
+        57 => "invalid return address",
+        58 => "unexpected host behavior",
+        59 => "flags corruption",
+        60 => "vectored exception handler corruption",
+        _ => return None,
+    })
+}
+
+/// Decodes the exception parameters (`ExceptionInformation`) where they say more than the code
+///
+/// I.e `write to 0x0 (null)` for an access violation.
+pub fn describe_exception(code: u32, parameters: &[usize]) -> Option<String> {
+    match (code, parameters) {
+        // Access violations and in-page errors share the first two parameters, an in-page error
+        // also has the NTSTATUS of the failed read
+        (0xC0000005 | 0xC0000006, [kind, address, rest @ ..]) => {
+            let access = match kind {
+                0 => format!("read from {}", describe_address(*address)),
+                1 => format!("write to {}", describe_address(*address)),
+                8 => format!("execute at {}", describe_address(*address)),
+                _ => format!("access ({}) at {}", kind, describe_address(*address)),
+            };
+            Some(match (code, rest) {
+                (0xC0000006, [status, ..]) => format!("{} (status {:#X})", access, status),
+                _ => access,
+            })
         }
+        (0xC0000409, [fail_fast, ..]) => Some(match fail_fast_code_to_str(*fail_fast) {
+            Some(reason) => format!("fail fast: {}", reason),
+            None => format!("fail fast code {}", fail_fast),
+        }),
+        _ => None,
     }
+}
 
-    let mut buf = [0u16; 260]; // MAX_PATH
-    unsafe {
-        let len = GetModuleFileNameW(Option::from(hmod), &mut buf);
-        if len == 0 {
-            return None;
+#[cfg(windows)]
+fn read_u32(address: usize) -> Option<u32> {
+    let mut buffer = [0u8; size_of::<u32>()];
+    read_memory(address, &mut buffer).then(|| u32::from_le_bytes(buffer))
+}
+
+/// Follows a 32 bit image relative offset at `address`, None for a null or unreadable one
+#[cfg(windows)]
+fn read_rva(image_base: usize, address: usize) -> Option<usize> {
+    match read_u32(address)? {
+        0 => None,
+        rva => Some(image_base + rva as usize),
+    }
+}
+
+/// `.?AVruntime_error@std@@` into `std::runtime_error`, anything more involved is left decorated
+#[cfg(windows)]
+fn undecorate_type_name(name: &str) -> String {
+    let simple = name
+        .strip_prefix(".?AV")
+        .or_else(|| name.strip_prefix(".?AU"))
+        .and_then(|rest| rest.strip_suffix("@@"))
+        .filter(|rest| !rest.contains(['?', '$']));
+    match simple {
+        Some(rest) => rest.rsplit('@').collect::<Vec<_>>().join("::"),
+        None => name.to_string(),
+    }
+}
+
+/// Pulls the type name out of a MSVC C++ exception
+///
+/// On x64 the throw info only holds offsets relative to the image that threw, which is the fourth
+/// parameter: ThrowInfo -> CatchableTypeArray -> the first CatchableType -> its TypeDescriptor.
+/// Every step is read through [`read_memory`], so a bogus record just gives None.
+#[cfg(windows)]
+fn cpp_exception_type(record: &EXCEPTION_RECORD) -> Option<String> {
+    // ThrowInfo { attributes, pmfnUnwind, pForwardCompat, pCatchableTypeArray }
+    const CATCHABLE_TYPE_ARRAY_OFFSET: usize = 12;
+    // CatchableTypeArray { nCatchableTypes, arrayOfCatchableTypes[] }
+    const CATCHABLE_TYPES_OFFSET: usize = 4;
+    // CatchableType { properties, pType, .. }
+    const TYPE_DESCRIPTOR_OFFSET: usize = 4;
+    // TypeDescriptor { pVFTable, spare, name[] }
+    const TYPE_NAME_OFFSET: usize = 2 * size_of::<usize>();
+    const MAX_NAME_LENGTH: usize = 256;
+
+    if record.NumberParameters < 4
+        || !(0x19930520..=0x19930522).contains(&record.ExceptionInformation[0])
+    {
+        return None;
+    }
+    let throw_info = record.ExceptionInformation[2];
+    let image_base = record.ExceptionInformation[3];
+    if throw_info == 0 || image_base == 0 {
+        return None;
+    }
+    let type_array = read_rva(image_base, throw_info + CATCHABLE_TYPE_ARRAY_OFFSET)?;
+    if read_u32(type_array)? == 0 {
+        return None;
+    }
+    let catchable_type = read_rva(image_base, type_array + CATCHABLE_TYPES_OFFSET)?;
+    let descriptor = read_rva(image_base, catchable_type + TYPE_DESCRIPTOR_OFFSET)?;
+    let mut name = Vec::new();
+    for index in 0..MAX_NAME_LENGTH {
+        let mut byte = [0u8];
+        if !read_memory(descriptor + TYPE_NAME_OFFSET + index, &mut byte) || byte[0] == 0 {
+            break;
+        }
+        name.push(byte[0]);
+    }
+    if name.is_empty() {
+        return None;
+    }
+    Some(undecorate_type_name(&String::from_utf8_lossy(&name)))
+}
+
+/// Reads up to [`STACK_WINDOW`] values from `rsp`, stopping at the first unreadable one
+#[cfg(windows)]
+fn read_stack(rsp: u64) -> Vec<u64> {
+    let mut values = Vec::with_capacity(STACK_WINDOW);
+    for index in 0..STACK_WINDOW {
+        let mut buffer = [0u8; size_of::<u64>()];
+        if !read_memory(rsp as usize + index * size_of::<u64>(), &mut buffer) {
+            break;
+        }
+        values.push(u64::from_le_bytes(buffer));
+    }
+    values
+}
+
+/// Best effort walk from the faulting context using the unwind data of each module
+///
+/// Returns the instruction pointer of every frame, innermost first.
+#[cfg(windows)]
+fn walk_stack(context: &CONTEXT) -> Vec<usize> {
+    let mut context = *context;
+    let mut frames = Vec::new();
+    while frames.len() < MAX_FRAMES && context.Rip != 0 {
+        frames.push(context.Rip as usize);
+        let rsp = context.Rsp;
+        let mut image_base = 0u64;
+        unsafe {
+            let entry = RtlLookupFunctionEntry(context.Rip, &mut image_base, None);
+            if entry.is_null() {
+                // Leaf function, the return address is right at RSP
+                let mut buffer = [0u8; size_of::<u64>()];
+                if !read_memory(rsp as usize, &mut buffer) {
+                    break;
+                }
+                context.Rip = u64::from_le_bytes(buffer);
+                context.Rsp += size_of::<u64>() as u64;
+            } else {
+                let mut handler_data = std::ptr::null_mut();
+                let mut establisher_frame = 0u64;
+                RtlVirtualUnwind(
+                    UNW_FLAG_NHANDLER,
+                    image_base,
+                    context.Rip,
+                    entry,
+                    &mut context,
+                    &mut handler_data,
+                    &mut establisher_frame,
+                    None,
+                );
+            }
         }
+        // The stack only grows one way, anything else means the unwind went wrong
+        if context.Rsp <= rsp {
+            break;
+        }
+    }
+    frames
+}
+
+/// Module+offset for `address`, going through the loader first like the rest of the crate
+#[cfg(windows)]
+fn resolve(modules: &[LoadedModule], address: usize) -> Option<ModuleOffset> {
+    module_from_address(address)
+        .map(|(base, module)| ModuleOffset {
+            module,
+            base,
+            offset: address - base,
+            symbol: None,
+        })
+        .or_else(|| locate(modules, address))
+}
+
+#[cfg(windows)]
+unsafe fn build_report(info: &EXCEPTION_POINTERS) -> CrashReport {
+    let (record, ctx) = unsafe { (&*info.ExceptionRecord, &*info.ContextRecord) };
+    let code = record.ExceptionCode.0 as u32;
+    let address = record.ExceptionAddress as usize;
+    let registers = Registers {
+        rax: ctx.Rax,
+        rbx: ctx.Rbx,
+        rcx: ctx.Rcx,
+        rdx: ctx.Rdx,
+        rsi: ctx.Rsi,
+        rdi: ctx.Rdi,
+        rbp: ctx.Rbp,
+        rsp: ctx.Rsp,
+        rip: ctx.Rip,
+        r8: ctx.R8,
+        r9: ctx.R9,
+        r10: ctx.R10,
+        r11: ctx.R11,
+        r12: ctx.R12,
+        r13: ctx.R13,
+        r14: ctx.R14,
+        r15: ctx.R15,
+        eflags: ctx.EFlags,
+    };
+    let mut report = CrashReport::new(code, address, registers, loaded_modules());
+    report.detail = describe_exception(
+        code,
+        &record.ExceptionInformation
+            [..(record.NumberParameters as usize).min(record.ExceptionInformation.len())],
+    );
+    report.location = resolve(&report.modules, address);
+    let own = own_modules();
+    report.frames = walk_stack(ctx)
+        .into_iter()
+        .map(|frame| StackFrame::new(frame, resolve(&report.modules, frame), &own))
+        .collect();
+    if code == CPP_EXCEPTION {
+        report.cpp_exception_type = cpp_exception_type(record);
+    }
+    report.set_stack(&read_stack(ctx.Rsp));
+    report.symbolize(crate::symbols::symbolize);
+    report.versions = detected_versions();
+    report.recent_log = recent_log();
+    report
+}
+
+#[cfg(windows)]
+const EXCEPTION_NONCONTINUABLE: u32 = 0x1;
+#[cfg(windows)]
+const EXCEPTION_CONTINUE_SEARCH: i32 = 0;
+
+#[cfg(windows)]
+static EXCEPTION_FILTER: LazyLock<Mutex<ExceptionFilter>> =
+    LazyLock::new(|| Mutex::new(ExceptionFilter::default()));
+
+#[cfg(windows)]
+thread_local! {
+    // Set while this thread is in the handler, so a fault while reporting doesn't recurse
+    static IN_HANDLER: Cell<bool> = const { Cell::new(false) };
+}
 
-        let full_path = String::from_utf16_lossy(&buf[..len as usize]);
-        let file_name = Path::new(&full_path)
-            .file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("<unknown>")
-            .to_string();
+/// The one line the vectored handler logs, whether the exception ends up handled or not
+///
+/// Exceptions the game likely handles can come in every frame, so those skip symbolizing and the
+/// C++ type lookup, only fatal ones get the full line.
+#[cfg(windows)]
+unsafe fn log_short(info: &EXCEPTION_POINTERS, class: ExceptionClass, decision: Decision) {
+    let record = unsafe { &*info.ExceptionRecord };
+    let code = record.ExceptionCode.0 as u32;
+    let address = record.ExceptionAddress as usize;
+    if class != ExceptionClass::Fatal {
+        match decision {
+            Decision::Repeat { total, suppressed } => log::debug!(
+                "First-chance exception {:#X} ({}) at {:#X}, likely handled (seen {} times, {} not logged)",
+                code,
+                exception_code_to_str(code),
+                address,
+                total,
+                suppressed
+            ),
+            _ => log::debug!(
+                "First-chance exception {:#X} ({}) at {:#X}, likely handled",
+                code,
+                exception_code_to_str(code),
+                address
+            ),
+        }
+        return;
+    }
+    let parameters = &record.ExceptionInformation
+        [..(record.NumberParameters as usize).min(record.ExceptionInformation.len())];
+    let location = match resolve(&[], address) {
+        Some(mut location) => {
+            location.symbol = crate::symbols::symbolize(&location.module, location.offset);
+            location.to_string()
+        }
+        None => format!("{:#X}", address),
+    };
+    let mut line = format!(
+        "Exception {:#X} ({}) at {}",
+        code,
+        exception_code_to_str(code),
+        location
+    );
+    if let Some(detail) = describe_exception(code, parameters) {
+        line.push_str(&format!(": {}", detail));
+    }
+    if code == CPP_EXCEPTION
+        && let Some(type_name) = cpp_exception_type(record)
+    {
+        line.push_str(&format!(" [{}]", type_name));
+    }
+    if let Decision::Repeat { total, suppressed } = decision {
+        line.push_str(&format!(" (seen {} times", total));
+        if suppressed > 0 {
+            line.push_str(&format!(", {} not logged", suppressed));
+        }
+        line.push(')');
+    }
+    log::warn!("{}, reporting it if nothing handles it", line);
+}
 
-        Some((hmod, file_name))
+#[cfg(windows)]
+unsafe fn report_fatal(info: &EXCEPTION_POINTERS) {
+    let report = unsafe { build_report(info) };
+    for line in report.summary() {
+        log::error!("{}", line);
+    }
+    log::error!("Stack trace:");
+    for line in report.frame_lines() {
+        log::error!("    {}", line);
+    }
+    for line in report.register_lines() {
+        log::debug!("{}", line);
     }
+    match report.write_to(&paths::logs_dir()) {
+        Ok(path) => log::error!("Crash report written to {}", path.display()),
+        Err(err) => log::error!("Unable to write crash report: {}", err),
+    }
+    crate::safe_mode::record_crash();
+    run_crash_callbacks();
+    show_upload_guidance();
 }
 
+/// First-chance, sees exceptions the game goes on to handle, so it only ever logs a short line
+#[cfg(windows)]
 unsafe extern "system" fn exception_handler(info: *mut EXCEPTION_POINTERS) -> i32 {
-    if info.is_null() {
+    if info.is_null() || IN_HANDLER.get() {
         return 0;
     }
 
     unsafe {
         let record = &*(*info).ExceptionRecord;
-
-        let code = record.ExceptionCode;
-        let address = record.ExceptionAddress as usize;
-        if code.is_ok() {
+        let code = record.ExceptionCode.0 as u32;
+        let class = classify_exception(code, record.ExceptionFlags & EXCEPTION_NONCONTINUABLE != 0);
+        if class == ExceptionClass::Ignored
+            || (class == ExceptionClass::LikelyHandled && !log::log_enabled!(log::Level::Debug))
+        {
             return 0;
         }
-        const CPP_EXCEPTION: i32 = 0xE06D7363u32 as i32;
-        if code.0 == CPP_EXCEPTION {
-            // TODO, this is jank af
-            log::error!("C++ Exception detected at {:?}", record.ExceptionAddress);
-
-            // ExceptionInformation[0] is a magic number for C++ EH
-            let magic = record.ExceptionInformation[0];
-            let frame = record.ExceptionInformation[1] as *const ();
-            let desc = record.ExceptionInformation[2] as *const ();
-
-            log::debug!("Magic: {:#X}", magic);
-            log::debug!("Frame: {:p}", frame);
-            log::debug!("Desc: {:p}", desc);
-
-            if !desc.is_null() {
-                let type_name_ptr = *(desc as *const *const i8);
-
-                if !type_name_ptr.is_null()
-                    && let Ok(cstr) = std::ffi::CStr::from_ptr(type_name_ptr).to_str()
-                {
-                    log::error!("C++ exception type: {}", cstr);
-                }
-            }
-        }
-
-        // Get the module+offset
-        if let Some((base, name)) = module_from_address(address as *const c_void) {
-            let offset = address - base.0 as usize;
-            log::error!(
-                "Exception {:#X} ({}) at {:#p} in {}+0x{:X}",
-                code.0,
-                exception_code_to_str(code.0 as u32),
-                address as *const c_void,
-                name,
-                offset
-            );
-        } else {
-            log::error!(
-                "Exception {:#X} ({}) at {:#p} (module unknown)",
-                code.0,
-                exception_code_to_str(code.0 as u32),
-                address as *const c_void
-            );
+        IN_HANDLER.set(true);
+        let decision = match EXCEPTION_FILTER.try_lock() {
+            Ok(mut filter) => filter.check(code, record.ExceptionAddress as usize, Instant::now()),
+            // Another thread is mid-log, the unhandled filter still reports it if it's fatal
+            Err(_) => Decision::Suppress,
+        };
+        if decision != Decision::Suppress {
+            log_short(&*info, class, decision);
         }
-        log::error!(
-            "Please upload the \"{}\" in your game's log folder to either Github or to the Archipelago game thread!",
-            LOG_NAME.get().unwrap_or(&"<Unknown log>".to_string())
-        );
-        let ctx = *(*info).ContextRecord;
-        // I could probably do this better but oh well.
-        log::debug!(
-            "RAX={:#018x} RBX={:#018x} RCX={:#018x}",
-            ctx.Rax,
-            ctx.Rbx,
-            ctx.Rcx
-        );
-        log::debug!(
-            "RDX={:#018x} RSI={:#018x} RDI={:#018x}",
-            ctx.Rdx,
-            ctx.Rsi,
-            ctx.Rdi
-        );
-        log::debug!(
-            "RBP={:#018x} RSP={:#018x} RIP={:#018x}",
-            ctx.Rbp,
-            ctx.Rsp,
-            ctx.Rip
-        );
-        log::debug!(
-            "R8 ={:#018x} R9 ={:#018x} R10={:#018x}",
-            ctx.R8,
-            ctx.R9,
-            ctx.R10
-        );
-        log::debug!(
-            "R11={:#018x} R12={:#018x} R13={:#018x}",
-            ctx.R11,
-            ctx.R12,
-            ctx.R13
-        );
-        log::debug!("R14={:#018x} R15={:#018x}", ctx.R14, ctx.R15);
+        IN_HANDLER.set(false);
     }
 
     0
 }
+
+#[cfg(windows)]
+static PREVIOUS_FILTER: OnceLock<LPTOP_LEVEL_EXCEPTION_FILTER> = OnceLock::new();
+
+/// Last-chance, only runs once nothing caught the exception, so this is where the full report is
+/// made before handing over to whatever filter was set before
+#[cfg(windows)]
+unsafe extern "system" fn unhandled_exception_filter(info: *const EXCEPTION_POINTERS) -> i32 {
+    if !info.is_null() && !IN_HANDLER.get() {
+        IN_HANDLER.set(true);
+        unsafe { report_fatal(&*info) };
+        IN_HANDLER.set(false);
+    }
+    match PREVIOUS_FILTER.get().copied().flatten() {
+        Some(previous) => unsafe { previous(info) },
+        None => EXCEPTION_CONTINUE_SEARCH,
+    }
+}
+
 static LOG_NAME: OnceLock<String> = OnceLock::new();
+static BUG_REPORT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Tells the user what to upload. The bug report bundle itself is built on the next launch (or by
+/// `bug_report`), not from the crashing process.
+pub(crate) fn show_upload_guidance() {
+    log::error!(
+        "Please upload the \"{}\" in your game's log folder to either Github or to the Archipelago game thread!",
+        LOG_NAME.get().unwrap_or(&"<Unknown log>".to_string())
+    );
+    if !BUG_REPORT_REQUESTED.swap(true, Ordering::SeqCst) {
+        crate::bug_report::request_bug_report();
+        log::error!(
+            "A bug report with everything needed will be written to the logs folder on the next launch (or right away by running bug_report), please attach it instead"
+        );
+    }
+}
 
 pub fn install_exception_handler(log_name: &str) {
     LOG_NAME.set(log_name.to_string()).unwrap();
+    crate::safe_mode::init_safe_mode();
+    let spawned = std::thread::Builder::new()
+        .name("bug-report".to_string())
+        .spawn(|| {
+            if let Some(Err(err)) = crate::bug_report::create_pending_bug_report() {
+                log::error!("Unable to create a bug report: {}", err);
+            }
+        });
+    if let Err(err) = spawned {
+        log::error!("Unable to start building the pending bug report: {}", err);
+    }
+    register_crash_callback("offline checks", DEFAULT_BUDGET, || {
+        if let Err(err) = crate::item_sync::save_offline_checks() {
+            log::error!("Unable to save offline checks: {}", err);
+        }
+    });
+    register_crash_callback(
+        "overlay queue",
+        DEFAULT_BUDGET,
+        crate::ui::overlay_messages::dump_queue,
+    );
+    crate::panic_handler::install_panic_hook();
+    #[cfg(windows)]
     unsafe {
         AddVectoredExceptionHandler(1, Some(exception_handler));
+        let _ = PREVIOUS_FILTER.set(SetUnhandledExceptionFilter(Some(
+            unhandled_exception_filter,
+        )));
+        log::debug!("Installed exception handler");
+    }
+    #[cfg(not(windows))]
+    log::debug!("Vectored exception handling is unavailable on this platform");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCESS_VIOLATION: u32 = 0xC0000005;
+
+    #[test]
+    fn describes_access_violations() {
+        assert_eq!(
+            describe_exception(ACCESS_VIOLATION, &[1, 0]).as_deref(),
+            Some("write to 0x0 (null)")
+        );
+        assert_eq!(
+            describe_exception(ACCESS_VIOLATION, &[0, 0x18]).as_deref(),
+            Some("read from 0x18 (near null)")
+        );
+        // DEP, executing from a page that isn't executable
+        assert_eq!(
+            describe_exception(ACCESS_VIOLATION, &[8, 0x7FF6_1234_0000]).as_deref(),
+            Some("execute at 0x7FF612340000")
+        );
+        assert_eq!(
+            describe_exception(0xC0000006, &[0, 0x1_4000_0000, 0xC000_009C]).as_deref(),
+            Some("read from 0x140000000 (status 0xC000009C)")
+        );
+    }
+
+    #[test]
+    fn missing_parameters_are_not_described() {
+        assert_eq!(describe_exception(ACCESS_VIOLATION, &[1]), None);
+        assert_eq!(describe_exception(0xC0000094, &[1, 0]), None);
+        assert_eq!(
+            describe_exception(0xC0000409, &[2]).as_deref(),
+            Some("fail fast: stack cookie check failure")
+        );
     }
-    log::debug!("Installed exception handler");
 }
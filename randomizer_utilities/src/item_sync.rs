@@ -6,7 +6,7 @@ use std::fs::File;
 use std::io::{BufReader, Write};
 use std::path::Path;
 use std::sync::Mutex;
-use std::sync::atomic::AtomicI64;
+use std::sync::atomic::{AtomicI64, Ordering};
 
 // Note this is all tailored for DMC games
 const SYNC_FILE_NAME: &str = "archipelago.json";
@@ -23,14 +23,26 @@ pub fn write_sync_data_file<S: DeserializeOwned + 'static>(
     data: SlotSyncInfo,
     client: &Client<S>,
 ) -> Result<(), Box<dyn Error>> {
-    let mut file = File::create(format!(
-        "{}{}",
-        crate::get_room_path(client)?,
-        SYNC_FILE_NAME
-    ))?;
     log::debug!("Writing sync file");
-    file.write_all(serde_json::to_string_pretty(&data)?.as_bytes())?;
-    file.flush()?;
+    write_atomically(
+        Path::new(&format!(
+            "{}{}",
+            crate::get_room_path(client)?,
+            SYNC_FILE_NAME
+        )),
+        &data,
+    )
+}
+
+/// Writes `data` next to `path` and renames it into place, so a crash halfway through leaves the
+/// previous file intact instead of a truncated one
+fn write_atomically(path: &Path, data: &SlotSyncInfo) -> Result<(), Box<dyn Error>> {
+    let temp_path = path.with_extension("json.tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(serde_json::to_string_pretty(data)?.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)?;
     Ok(())
 }
 
@@ -71,9 +83,53 @@ pub fn read_save_data<S: DeserializeOwned + 'static>(
 
 pub static OFFLINE_CHECKS: Mutex<Vec<i64>> = Mutex::new(Vec::new());
 pub fn add_offline_check(location: i64) {
+    log::debug!(location_id = location; "Stored offline check");
     OFFLINE_CHECKS.lock().unwrap().push(location);
 }
 
+/// Moves [`CURRENT_INDEX`] past an item that was just handed to the player
+pub fn mark_item_received(index: i64, item_id: i64) {
+    log::debug!(index = index, item_id = item_id; "Received item");
+    CURRENT_INDEX.store(index + 1, Ordering::SeqCst);
+}
+
+/// Reads the current room's sync file, lets `update` change it and writes it back, doing nothing
+/// if no room is connected
+fn update_room_sync_file(update: impl FnOnce(&mut SlotSyncInfo)) -> Result<(), Box<dyn Error>> {
+    let Some(room) = crate::bug_report::current_room_dir() else {
+        return Ok(());
+    };
+    let path = room.join(SYNC_FILE_NAME);
+    let mut sync_data = match File::open(&path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+        Err(_) => SlotSyncInfo::default(),
+    };
+    update(&mut sync_data);
+    write_atomically(&path, &sync_data)
+}
+
+/// Adds any offline checks still in memory to the current room's sync file, used as a crash
+/// callback since those would otherwise be gone with the process
+pub fn save_offline_checks() -> Result<(), Box<dyn Error>> {
+    let checks = match OFFLINE_CHECKS.try_lock() {
+        Ok(checks) => checks.clone(),
+        Err(_) => return Err("Offline checks are locked".into()),
+    };
+    if checks.is_empty() {
+        return Ok(());
+    }
+    update_room_sync_file(|sync_data| {
+        let mut added = 0;
+        for check in checks {
+            if !sync_data.offline_checks.contains(&check) {
+                sync_data.offline_checks.push(check);
+                added += 1;
+            }
+        }
+        log::info!("Saved {} offline checks", added);
+    })
+}
+
 pub fn send_offline_checks<T: DeserializeOwned>(
     client: &mut Client<T>,
 ) -> Result<(), Box<dyn Error>> {
@@ -96,3 +152,45 @@ pub fn send_offline_checks<T: DeserializeOwned>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// The room dir is global, tests writing to it take turns
+    static ROOM: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn sync_info_round_trips() {
+        let mut info = SlotSyncInfo::default();
+        info.sync_index[2] = 17;
+        info.offline_checks = vec![1, 2, 3];
+        let parsed: SlotSyncInfo =
+            serde_json::from_str(&serde_json::to_string_pretty(&info).unwrap()).unwrap();
+        assert_eq!(parsed.sync_index, info.sync_index);
+        assert_eq!(parsed.offline_checks, info.offline_checks);
+    }
+
+    #[test]
+    fn offline_checks_are_merged_into_the_sync_file() {
+        let _room = ROOM.lock().unwrap();
+        let room = crate::paths::test_data_root().join("archipelago/item_sync_room");
+        fs::create_dir_all(&room).unwrap();
+        fs::write(
+            room.join(SYNC_FILE_NAME),
+            r#"{ "sync_index": [5, 0, 0, 0, 0, 0, 0, 0, 0, 0], "offline_checks": [10] }"#,
+        )
+        .unwrap();
+        crate::bug_report::set_room_dir(room.clone());
+        for check in [10, 11, 12] {
+            add_offline_check(check);
+        }
+        save_offline_checks().unwrap();
+
+        let saved: SlotSyncInfo =
+            serde_json::from_str(&fs::read_to_string(room.join(SYNC_FILE_NAME)).unwrap()).unwrap();
+        assert_eq!(saved.sync_index[0], 5);
+        assert_eq!(saved.offline_checks, vec![10, 11, 12]);
+    }
+}
@@ -1,115 +1,55 @@
 use archipelago_rs::Client;
-use figment::Figment;
-use figment::providers::{Format, Toml};
-use log::LevelFilter;
-use log4rs::append::console::ConsoleAppender;
-use log4rs::append::rolling_file::RollingFileAppender;
-use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
-use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
-use log4rs::append::rolling_file::policy::compound::trigger::onstartup::OnStartUpTrigger;
-use log4rs::config::{Appender, Logger, Root};
-use log4rs::encode::pattern::PatternEncoder;
-use log4rs::{Config, Handle};
+use memory::{MemoryAccessorExt, Pod};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::ffi::OsStr;
 use std::fmt::Display;
-use std::os::windows::ffi::OsStrExt;
-use std::path::Path;
 use std::sync::OnceLock;
 use std::sync::mpsc::{Receiver, Sender};
-use std::{fs, ptr, sync};
-use windows::Win32::Foundation::GetLastError;
-use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-use windows::Win32::System::Memory::{
-    PAGE_EXECUTE_READWRITE, PAGE_PROTECTION_FLAGS, VirtualProtect,
-};
-use windows::core::PCWSTR;
+use std::{fs, sync};
 
 pub mod archipelago_utilities;
+pub mod bug_report;
+pub mod config;
+pub mod crash_callbacks;
+pub mod crash_report;
 #[cfg(feature = "dmc")]
 pub mod dmc;
+pub mod exception_filter;
 pub mod exception_handler;
 pub mod item_sync;
+pub mod logging;
+pub mod memory;
+pub mod panic_handler;
+pub mod patches;
+pub mod paths;
+pub mod platform;
+pub mod safe_mode;
+pub mod scanner;
+pub mod symbols;
 pub mod ui;
 
+pub use config::{load_config, save_config};
+pub use logging::setup_logger;
+pub use platform::{get_base_address, is_library_loaded};
+
 pub type BasicNothingFunc = unsafe extern "system" fn();
 
-/// Default config for log files
-///
-/// # Arguments
-///
-/// * `prefix`: Prefix for the log file (I.e dmc3_rando.log)
+/// Reads <T> data from a provided offset, validating the address first
 ///
-/// returns: Handle
-pub fn setup_logger(prefix: &str) -> Handle {
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d} {h({l})} {t} - {m}{n}")))
-        .build();
-
-    let log_file = RollingFileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new("{d} {l} {t} - {m}{n}")))
-        .append(false)
-        .build(
-            format!("logs/{}_latest.log", prefix),
-            Box::new(CompoundPolicy::new(
-                Box::new(OnStartUpTrigger::new(10)), // 0x35c Rough guess based on the usual log output I spill out
-                Box::new(
-                    FixedWindowRoller::builder()
-                        .build(format!("logs/{}_{}.log", prefix, "{}").as_str(), 3)
-                        .unwrap(),
-                ),
-            )),
-        )
-        .unwrap();
-
-    let config = Config::builder()
-        .appender(Appender::builder().build("stdout", Box::new(stdout)))
-        .appender(Appender::builder().build("log_file", Box::new(log_file)))
-        .logger(Logger::builder().build("tracing::span", LevelFilter::Warn))
-        .logger(Logger::builder().build("minhook", LevelFilter::Warn))
-        .build(
-            Root::builder()
-                .appender("stdout")
-                .appender("log_file")
-                .build(LevelFilter::Debug),
-        )
-        .unwrap();
-
-    log4rs::init_config(config).unwrap()
-}
-
-pub fn is_library_loaded(name: &str) -> bool {
-    let wide_name: Vec<u16> = OsStr::new(name)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-    unsafe {
-        if let Ok(module_handle) = GetModuleHandleW(PCWSTR::from_raw(wide_name.as_ptr())) {
-            !module_handle.is_invalid()
-        } else {
-            false
-        }
-    }
-}
-
-/// Generic method to get the base address for the specified module, returns 0 if it doesn't exist
-pub fn get_base_address(module_name: &str) -> usize {
-    let wide_name: Vec<u16> = OsStr::new(&module_name)
-        .encode_wide()
-        .chain(std::iter::once(0))
-        .collect();
-    unsafe {
-        if let Ok(module_handle) = GetModuleHandleW(PCWSTR::from_raw(wide_name.as_ptr())) {
-            module_handle.0 as usize
-        } else {
-            0
-        }
-    }
+/// Prefer this over [`read_data_from_address`] for offsets that might be wrong for the running
+/// build, since a bad address comes back as an error instead of crashing the game.
+pub fn try_read_data_from_address<T>(address: usize) -> Result<T, memory::MemoryError>
+where
+    T: Pod,
+{
+    memory::accessor().read_checked::<T>(address)
 }
 
 /// Reads <T> data from a provided offset
+///
+/// Unchecked, a bad address crashes the game. See [`try_read_data_from_address`] for a checked
+/// read.
 pub fn read_data_from_address<T>(address: usize) -> T
 where
     T: Copy,
@@ -117,44 +57,19 @@ where
     unsafe { *(address as *const T) }
 }
 
-const ARCHIPELAGO: &str = "archipelago";
-
-/// Loads or create a config file with the given name and struct.
+/// Makes the memory at `offset` writable while `f` runs, then restores its protection
 ///
-/// This will also remake the config if the file cannot be read
-pub fn load_config<T>(config_name: &str) -> Result<T, Box<dyn Error>>
+/// Goes through [`memory::accessor`] like the rest of the memory helpers.
+pub fn modify_protected_memory<F, R, T>(f: F, offset: *mut T) -> Result<R, Box<dyn Error>>
 where
-    T: Default + serde::ser::Serialize + serde::de::Deserialize<'static>,
+    F: FnOnce() -> R,
 {
-    if !fs::exists(ARCHIPELAGO)? {
-        fs::create_dir(ARCHIPELAGO)?;
-    }
-    let config_path = format!("{}/{}.toml", ARCHIPELAGO, config_name);
-    if !Path::new(&config_path).exists() {
-        log::debug!("Config file not found. Creating a default one.");
-        let toml_string =
-            toml::to_string(&T::default()).expect("Could not serialize default config");
-        fs::write(&config_path, toml_string).expect("Could not write default config to file");
-    }
-    match Figment::new()
-        .merge(Toml::file(&config_path))
-        .extract::<T>()
-    {
-        Ok(config) => Ok(config),
-        Err(err) => {
-            log::warn!("Failed to parse config: {err}. Backing up and regenerating.");
-
-            let backup_path = format!("{}/{}.old.toml", ARCHIPELAGO, config_name);
-            fs::rename(&config_path, &backup_path)?;
-            log::info!("Old config backed up to {backup_path}");
-
-            let toml_string =
-                toml::to_string(&T::default()).expect("Could not serialize default config");
-            fs::write(&config_path, &toml_string)?;
-
-            Ok(T::default())
-        }
-    }
+    let mut f = Some(f);
+    let mut result = None;
+    memory::accessor().unprotect(offset as usize, size_of::<T>(), &mut || {
+        result = f.take().map(|f| f());
+    })?;
+    result.ok_or_else(|| "Protected memory closure did not run".into())
 }
 
 pub fn setup_channel_pair<T>(channel: &OnceLock<Sender<T>>) -> Receiver<T> {
@@ -163,43 +78,18 @@ pub fn setup_channel_pair<T>(channel: &OnceLock<Sender<T>>) -> Receiver<T> {
     rx
 }
 
-pub fn modify_protected_memory<F, R, T>(f: F, offset: *mut T) -> Result<R, Box<dyn Error>>
-where
-    F: FnOnce() -> R,
-{
-    let length = size_of::<T>();
-    let mut old_protect = PAGE_PROTECTION_FLAGS::default();
-    unsafe {
-        if VirtualProtect(
-            offset as *mut _,
-            length,
-            PAGE_EXECUTE_READWRITE,
-            &mut old_protect,
-        )
-        .is_err()
-        {
-            return Err(format!("Failed to use VirtualProtect (1): {:?}", GetLastError()).into());
-        }
-        let res = f();
-        if VirtualProtect(offset as *mut _, length, old_protect, &mut old_protect).is_err() {
-            return Err(format!("Failed to use VirtualProtect (2): {:?}", GetLastError()).into());
-        }
-        Ok(res)
-    }
-}
 /// Replaces a single byte at the specified address
 ///
+/// The old byte isn't kept, use [`patches::PATCHES`] for anything that might need to be reverted.
+///
 /// # Safety
 ///
 /// Relies on me not screwing up
 pub unsafe fn replace_single_byte(offset_orig: usize, new_value: u8) {
-    let offset = offset_orig as *mut u8;
-    match modify_protected_memory(
-        || unsafe {
-            ptr::write(offset, new_value);
-        },
-        offset,
-    ) {
+    if safe_mode::is_safe_mode() {
+        return;
+    }
+    match memory::accessor().write_protected(offset_orig, &[new_value]) {
         Ok(()) => {
             const LOG_BYTE_REPLACEMENTS: bool = false;
             if LOG_BYTE_REPLACEMENTS {
@@ -234,11 +124,15 @@ impl Display for APVersion {
 pub fn get_room_path<S: DeserializeOwned + 'static>(
     client: &Client<S>,
 ) -> Result<String, Box<dyn Error>> {
-    let path = format!(
-        "archipelago/{}_{}/",
+    let path = paths::archipelago_dir().join(format!(
+        "{}_{}",
         client.seed_name(),
         client.this_player().name()
-    );
+    ));
     fs::create_dir_all(&path)?;
-    Ok(path)
+    logging::redaction::add_secret(client.this_player().name());
+    logging::json::set_context("seed", client.seed_name());
+    logging::json::set_context("slot", client.this_player().name());
+    bug_report::set_room_dir(path.clone());
+    Ok(format!("{}{}", path.display(), std::path::MAIN_SEPARATOR))
 }
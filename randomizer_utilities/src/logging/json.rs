@@ -0,0 +1,135 @@
+//! JSON lines output for the log file, so logs can be filtered by field rather than by regex.
+//!
+//! Each line holds `timestamp`, `level`, `target`, `thread` and `message`, then a `context` object
+//! with the session context set through [`set_context`] (I.e `seed`, `slot`) and a `fields` object
+//! with any key-values on the record itself
+//! (`log::info!(location_id = 123, item_id = 45; "Checked location")`). Keeping those nested means
+//! they can't overwrite the fixed fields.
+use log::Record;
+use log::kv::{Key, VisitSource};
+use log4rs::encode::{Encode, Write};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::sync::{LazyLock, RwLock};
+
+/// Format used for the log file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+static CONTEXT: LazyLock<RwLock<Map<String, Value>>> = LazyLock::new(|| RwLock::new(Map::new()));
+
+/// Adds a field to every JSON log line from now on (I.e the seed and slot once connected)
+pub fn set_context<V: Into<Value>>(key: &str, value: V) {
+    match CONTEXT.write() {
+        Ok(mut context) => {
+            context.insert(key.to_string(), value.into());
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to set log context {:?}", err);
+        }
+    }
+}
+
+pub fn clear_context(key: &str) {
+    if let Ok(mut context) = CONTEXT.write() {
+        context.remove(key);
+    }
+}
+
+struct FieldCollector<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for FieldCollector<'_> {
+    fn visit_pair(
+        &mut self,
+        key: Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = if let Some(value) = value.to_i64() {
+            Value::from(value)
+        } else if let Some(value) = value.to_u64() {
+            Value::from(value)
+        } else if let Some(value) = value.to_f64() {
+            Value::from(value)
+        } else if let Some(value) = value.to_bool() {
+            Value::from(value)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.as_str().to_string(), value);
+        Ok(())
+    }
+}
+
+/// Builds the JSON object for a single record
+pub fn record_to_json(record: &Record, timestamp: &str, thread: &str) -> Map<String, Value> {
+    let mut line = Map::new();
+    line.insert("timestamp".to_string(), Value::from(timestamp));
+    line.insert("level".to_string(), Value::from(record.level().as_str()));
+    line.insert("target".to_string(), Value::from(record.target()));
+    line.insert("thread".to_string(), Value::from(thread));
+    line.insert(
+        "message".to_string(),
+        Value::from(record.args().to_string()),
+    );
+    if let Ok(context) = CONTEXT.read()
+        && !context.is_empty()
+    {
+        line.insert("context".to_string(), Value::Object(context.clone()));
+    }
+    let mut fields = Map::new();
+    // Failing to read the key-values still leaves the rest of the line intact
+    let _ = record.key_values().visit(&mut FieldCollector(&mut fields));
+    if !fields.is_empty() {
+        line.insert("fields".to_string(), Value::Object(fields));
+    }
+    line
+}
+
+/// Writes each record as a single line of JSON
+#[derive(Debug)]
+pub struct JsonLinesEncoder;
+
+impl Encode for JsonLinesEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let thread = std::thread::current();
+        let line = record_to_json(
+            record,
+            &chrono::Local::now().to_rfc3339(),
+            thread.name().unwrap_or("<unnamed>"),
+        );
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_and_fields_cannot_replace_fixed_fields() {
+        set_context("message", "from context");
+        let fields = [("level", 7)];
+        let line = record_to_json(
+            &Record::builder()
+                .level(log::Level::Info)
+                .target("tests")
+                .args(format_args!("Checked location"))
+                .key_values(&fields)
+                .build(),
+            "2026-01-01T00:00:00+00:00",
+            "main",
+        );
+        clear_context("message");
+        assert_eq!(line["message"], "Checked location");
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["context"]["message"], "from context");
+        assert_eq!(line["fields"]["level"], 7);
+    }
+}
@@ -0,0 +1,408 @@
+//! Logger setup, driven by `archipelago/logging.toml`.
+//!
+//! Levels are checked by [`LevelTableFilter`] on every appender instead of by log4rs loggers, so
+//! they can be changed at runtime through [`set_level`]/[`set_module_level`] (or by editing the
+//! file while the config watcher runs) without rebuilding any appenders.
+use crate::config::watcher::{subscribe, watch_config};
+use crate::logging::json::{JsonLinesEncoder, LogFormat};
+use crate::logging::redaction::{RedactingEncoder, RedactionConfig, Redactor, apply_redaction};
+use crate::logging::ring_buffer::RingBufferAppender;
+use crate::paths;
+use log::{LevelFilter, Record};
+use log4rs::append::console::ConsoleAppender;
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::fixed_window::FixedWindowRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::Trigger;
+use log4rs::append::rolling_file::policy::compound::trigger::onstartup::OnStartUpTrigger;
+use log4rs::append::rolling_file::{LogFile, RollingFileAppender};
+use log4rs::config::{Appender, Root};
+use log4rs::encode::Encode;
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::filter::{Filter, Response};
+use log4rs::{Config, Handle};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, RwLock};
+
+pub mod json;
+pub mod redaction;
+pub mod ring_buffer;
+
+const LOGGING_CONFIG: &str = "logging";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Level for anything without a more specific entry in `modules`
+    pub level: LevelFilter,
+    /// Levels keyed by target prefix (I.e "randomizer_utilities::item_sync")
+    pub modules: BTreeMap<String, LevelFilter>,
+    pub console: bool,
+    pub console_pattern: String,
+    /// `text` uses `file_pattern`, `json` writes one JSON object per line instead
+    pub file_format: LogFormat,
+    pub file_pattern: String,
+    /// How many old log files are kept around
+    pub retained_files: u32,
+    /// Also roll over once the log grows past this many bytes, not just at startup
+    pub max_file_size: Option<u64>,
+    /// How many recent records are kept in memory for crash reports and debug panels
+    pub memory_records: usize,
+    /// What gets masked before it reaches the log file
+    pub redaction: RedactionConfig,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Debug,
+            modules: BTreeMap::from([
+                ("tracing::span".to_string(), LevelFilter::Warn),
+                ("minhook".to_string(), LevelFilter::Warn),
+            ]),
+            console: true,
+            console_pattern: "{d} {h({l})} {t} - {m}{n}".to_string(),
+            file_format: LogFormat::Text,
+            file_pattern: "{d} {l} {t} - {m}{n}".to_string(),
+            retained_files: 3,
+            max_file_size: None,
+            memory_records: 500,
+            redaction: RedactionConfig::default(),
+        }
+    }
+}
+
+fn validate(config: &LoggingConfig) -> Result<(), String> {
+    if config.retained_files == 0 {
+        return Err("retained_files has to be at least 1".to_string());
+    }
+    if let Err(err) = Redactor::new(&config.redaction) {
+        return Err(format!("Invalid redaction pattern: {err}"));
+    }
+    Ok(())
+}
+
+/// Drops the entries [`validate`] would reject instead of the whole config, returning a message
+/// for each of them
+fn sanitize(config: &mut LoggingConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    if config.retained_files == 0 {
+        config.retained_files = LoggingConfig::default().retained_files;
+        problems.push(format!(
+            "retained_files has to be at least 1, using {}",
+            config.retained_files
+        ));
+    }
+    config
+        .redaction
+        .patterns
+        .retain(|pattern| match Regex::new(pattern) {
+            Ok(_) => true,
+            Err(err) => {
+                problems.push(format!("Ignoring invalid redaction pattern: {err}"));
+                false
+            }
+        });
+    problems
+}
+
+struct LevelTable {
+    default: LevelFilter,
+    // Longest prefix first, so the most specific entry wins
+    modules: Vec<(String, LevelFilter)>,
+}
+
+/// Whether `target` is `module` or one of its submodules, so `foo` matches `foo::bar` but not
+/// `foobar`
+pub(crate) fn target_in_module(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+impl LevelTable {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| target_in_module(target, module))
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+
+    fn set_module(&mut self, module: &str, level: Option<LevelFilter>) {
+        self.modules.retain(|(existing, _)| existing != module);
+        if let Some(level) = level {
+            self.modules.push((module.to_string(), level));
+        }
+        self.modules
+            .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+    }
+}
+
+static LEVELS: LazyLock<RwLock<LevelTable>> = LazyLock::new(|| {
+    RwLock::new(LevelTable {
+        default: LevelFilter::Debug,
+        modules: Vec::new(),
+    })
+});
+
+/// Lets through records at or above the level currently set for their target
+#[derive(Debug)]
+pub struct LevelTableFilter;
+
+impl Filter for LevelTableFilter {
+    fn filter(&self, record: &Record) -> Response {
+        match LEVELS.read() {
+            Ok(levels) if record.level() > levels.level_for(record.target()) => Response::Reject,
+            _ => Response::Neutral,
+        }
+    }
+}
+
+fn update_levels<F: FnOnce(&mut LevelTable)>(f: F) {
+    match LEVELS.write() {
+        Ok(mut levels) => {
+            f(&mut levels);
+            // Keeps the log macros from formatting records nobody is going to see
+            log::set_max_level(levels.max());
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to change log levels {:?}", err);
+        }
+    }
+}
+
+/// Sets the level used for targets without a module specific level
+pub fn set_level(level: LevelFilter) {
+    update_levels(|levels| levels.default = level);
+}
+
+/// Sets the level for `module` and everything under it (I.e "randomizer_utilities::item_sync")
+pub fn set_module_level(module: &str, level: LevelFilter) {
+    update_levels(|levels| levels.set_module(module, Some(level)));
+}
+
+/// Removes a module specific level, falling back to the next most specific one
+pub fn reset_module_level(module: &str) {
+    update_levels(|levels| levels.set_module(module, None));
+}
+
+/// Replaces every level with the ones from `config`
+pub fn apply_levels(config: &LoggingConfig) {
+    update_levels(|levels| {
+        levels.default = config.level;
+        levels.modules.clear();
+        for (module, level) in &config.modules {
+            levels.set_module(module, Some(*level));
+        }
+    });
+}
+
+/// Rolls the log over at startup like [`OnStartUpTrigger`], and optionally once it gets too big
+#[derive(Debug)]
+struct StartupOrSizeTrigger {
+    startup: OnStartUpTrigger,
+    max_size: Option<u64>,
+}
+
+impl Trigger for StartupOrSizeTrigger {
+    fn trigger(&self, file: &LogFile) -> anyhow::Result<bool> {
+        if self.startup.trigger(file)? {
+            return Ok(true);
+        }
+        Ok(self.max_size.is_some_and(|max| file.len_estimate() > max))
+    }
+
+    fn is_pre_process(&self) -> bool {
+        true
+    }
+}
+
+/// Sets up logging from `archipelago/logging.toml`
+///
+/// Levels follow the file while the config watcher is running, the rest of the settings are only
+/// read here.
+///
+/// # Arguments
+///
+/// * `prefix`: Prefix for the log file (I.e dmc3_rando.log)
+///
+/// returns: Handle
+pub fn setup_logger(prefix: &str) -> Handle {
+    // Nothing can be logged until the logger below is up, so problems are collected until then
+    let mut problems = Vec::new();
+    let mut logging_config = match watch_config::<LoggingConfig>(LOGGING_CONFIG, validate) {
+        Ok(config) => {
+            subscribe::<LoggingConfig, _>(LOGGING_CONFIG, apply_levels);
+            subscribe::<LoggingConfig, _>(LOGGING_CONFIG, |config| {
+                apply_redaction(&config.redaction)
+            });
+            (*config).clone()
+        }
+        Err(err) => {
+            problems.push(format!(
+                "Unable to load logging config, using defaults: {err}"
+            ));
+            LoggingConfig::default()
+        }
+    };
+    problems.extend(sanitize(&mut logging_config));
+
+    let (encoder, extension): (Box<dyn Encode>, &str) = match logging_config.file_format {
+        LogFormat::Text => (
+            Box::new(PatternEncoder::new(&logging_config.file_pattern)),
+            "log",
+        ),
+        LogFormat::Json => (Box::new(JsonLinesEncoder), "jsonl"),
+    };
+    apply_redaction(&logging_config.redaction);
+    let log_file = RollingFileAppender::builder()
+        .encoder(Box::new(RedactingEncoder::new(encoder)))
+        .append(false)
+        .build(
+            paths::logs_dir().join(format!("{}_latest.{}", prefix, extension)),
+            Box::new(CompoundPolicy::new(
+                Box::new(StartupOrSizeTrigger {
+                    startup: OnStartUpTrigger::new(10), // 0x35c Rough guess based on the usual log output I spill out
+                    max_size: logging_config.max_file_size,
+                }),
+                Box::new(
+                    FixedWindowRoller::builder()
+                        .build(
+                            &paths::logs_dir()
+                                .join(format!("{}_{}.{}", prefix, "{}", extension))
+                                .to_string_lossy(),
+                            logging_config.retained_files,
+                        )
+                        .unwrap(),
+                ),
+            )),
+        )
+        .unwrap();
+
+    let mut config = Config::builder().appender(
+        Appender::builder()
+            .filter(Box::new(LevelTableFilter))
+            .build("log_file", Box::new(log_file)),
+    );
+    let mut root = Root::builder().appender("log_file");
+    ring_buffer::set_capacity(logging_config.memory_records);
+    if logging_config.memory_records > 0 {
+        config = config.appender(
+            Appender::builder()
+                .filter(Box::new(LevelTableFilter))
+                .build("memory", Box::new(RingBufferAppender)),
+        );
+        root = root.appender("memory");
+    }
+    if logging_config.console {
+        let stdout = ConsoleAppender::builder()
+            .encoder(Box::new(RedactingEncoder::new(Box::new(
+                PatternEncoder::new(&logging_config.console_pattern),
+            ))))
+            .build();
+        config = config.appender(
+            Appender::builder()
+                .filter(Box::new(LevelTableFilter))
+                .build("stdout", Box::new(stdout)),
+        );
+        root = root.appender("stdout");
+    }
+
+    // Everything is let through here, LevelTableFilter does the actual filtering
+    let handle =
+        log4rs::init_config(config.build(root.build(LevelFilter::Trace)).unwrap()).unwrap();
+    apply_levels(&logging_config);
+    paths::log_data_root();
+    for problem in problems {
+        log::warn!("{}", problem);
+    }
+    handle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_only_drops_invalid_entries() {
+        let mut config = LoggingConfig {
+            level: LevelFilter::Info,
+            retained_files: 0,
+            ..LoggingConfig::default()
+        };
+        config.redaction.patterns = vec!["token-[0-9]+".to_string(), "(".to_string()];
+        let problems = sanitize(&mut config);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(config.level, LevelFilter::Info);
+        assert_eq!(config.retained_files, 3);
+        assert_eq!(config.redaction.patterns, vec!["token-[0-9]+".to_string()]);
+        assert!(validate(&config).is_ok());
+        assert!(sanitize(&mut config).is_empty());
+    }
+
+    fn table(entries: &[(&str, LevelFilter)]) -> LevelTable {
+        let mut table = LevelTable {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        };
+        for (module, level) in entries {
+            table.set_module(module, Some(*level));
+        }
+        table
+    }
+
+    #[test]
+    fn longest_module_prefix_wins() {
+        let table = table(&[
+            ("game", LevelFilter::Warn),
+            ("game::item_sync", LevelFilter::Trace),
+        ]);
+        assert_eq!(
+            table.level_for("game::item_sync::offline"),
+            LevelFilter::Trace
+        );
+        assert_eq!(table.level_for("game::item_sync"), LevelFilter::Trace);
+        assert_eq!(table.level_for("game::overlay"), LevelFilter::Warn);
+        assert_eq!(table.level_for("other"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn modules_only_match_whole_path_segments() {
+        let table = table(&[("foo", LevelFilter::Trace)]);
+        assert_eq!(table.level_for("foo"), LevelFilter::Trace);
+        assert_eq!(table.level_for("foo::bar"), LevelFilter::Trace);
+        assert_eq!(table.level_for("foobar"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn resetting_a_module_falls_back_to_the_next_entry() {
+        let mut table = table(&[
+            ("game", LevelFilter::Warn),
+            ("game::item_sync", LevelFilter::Trace),
+        ]);
+        table.set_module("game::item_sync", None);
+        assert_eq!(table.level_for("game::item_sync"), LevelFilter::Warn);
+        table.set_module("game", None);
+        assert_eq!(table.level_for("game::item_sync"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn max_covers_the_default_and_every_module() {
+        let mut table = table(&[("game", LevelFilter::Error)]);
+        assert_eq!(table.max(), LevelFilter::Info);
+        table.set_module("game::item_sync", Some(LevelFilter::Trace));
+        assert_eq!(table.max(), LevelFilter::Trace);
+        table.default = LevelFilter::Off;
+        table.set_module("game::item_sync", None);
+        assert_eq!(table.max(), LevelFilter::Error);
+    }
+}
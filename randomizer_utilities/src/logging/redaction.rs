@@ -0,0 +1,567 @@
+//! Masks passwords, known secrets and user names in home paths before log output reaches disk.
+//!
+//! Rules come from the `[redaction]` table in `logging.toml`, values only known at runtime (I.e
+//! the room password once connected) can be added through [`add_secret`].
+use log::Record;
+use log4rs::encode::writer::simple::SimpleWriter;
+use log4rs::encode::{Encode, Write};
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::sync::{LazyLock, RwLock};
+
+pub const MASK: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    /// Values following these keys are masked (I.e `password=hunter2`, `"password": "hunter2"`)
+    pub keys: Vec<String>,
+    /// Masks the user name in `C:\Users\<name>` and `/home/<name>`
+    pub home_paths: bool,
+    /// Exact values that are always masked
+    pub secrets: Vec<String>,
+    /// Extra regular expressions, the whole match gets masked
+    pub patterns: Vec<String>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keys: vec![
+                "password".to_string(),
+                "passwd".to_string(),
+                "token".to_string(),
+                "secret".to_string(),
+                "api_key".to_string(),
+            ],
+            home_paths: true,
+            secrets: Vec::new(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+/// Compiled set of redaction rules
+#[derive(Debug, Default)]
+pub struct Redactor {
+    enabled: bool,
+    /// Lowercase, for picking secrets out of configs
+    key_names: Vec<String>,
+    keys: Option<Regex>,
+    home_paths: Vec<Regex>,
+    secrets: Vec<String>,
+    /// Added through [`Redactor::add_secret`], kept when the rules are replaced
+    runtime_secrets: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Builds the rules from `config`, invalid custom patterns are returned as an error
+    pub fn new(config: &RedactionConfig) -> Result<Self, regex::Error> {
+        let keys = if config.keys.is_empty() {
+            None
+        } else {
+            let keys = config
+                .keys
+                .iter()
+                .map(|key| regex::escape(key))
+                .collect::<Vec<_>>()
+                .join("|");
+            // key, optional closing quote, separator, optional Some( from Debug output, then the
+            // value, which is either quoted (possibly escaped, I.e inside a JSON string) or runs
+            // until the next separator
+            Some(
+                RegexBuilder::new(&format!(
+                    r#"((?:{keys})\\?["']?\s*[:=]\s*(?:Some\(\s*)?)(?:\\"((?:[^"\\]|\\[^"])*)\\"|"((?:[^"\\]|\\.)*)"|'((?:[^'\\]|\\.)*)'|([^\s"',;}}\])]+))"#
+                ))
+                .case_insensitive(true)
+                .build()?,
+            )
+        };
+        let home_paths = if config.home_paths {
+            vec![
+                // Separators can show up escaped (I.e in JSON or Debug output)
+                RegexBuilder::new(r#"([a-z]:[\\/]+users[\\/]+)([^\\/\s"':;]+)"#)
+                    .case_insensitive(true)
+                    .build()?,
+                Regex::new(r#"(/home/)([^/\s"':;]+)"#)?,
+            ]
+        } else {
+            Vec::new()
+        };
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            enabled: config.enabled,
+            key_names: config.keys.iter().map(|key| key.to_lowercase()).collect(),
+            keys,
+            home_paths,
+            secrets: config
+                .secrets
+                .iter()
+                .filter(|secret| !secret.is_empty())
+                .cloned()
+                .collect(),
+            runtime_secrets: Vec::new(),
+            patterns,
+        })
+    }
+
+    pub fn add_secret(&mut self, secret: &str) {
+        if !secret.is_empty()
+            && !self.secrets.iter().any(|existing| existing == secret)
+            && !self
+                .runtime_secrets
+                .iter()
+                .any(|existing| existing == secret)
+        {
+            self.runtime_secrets.push(secret.to_string());
+        }
+    }
+
+    /// Swaps in the rules from `config`, keeping secrets added through [`Redactor::add_secret`]
+    pub fn replace_rules(&mut self, config: &RedactionConfig) -> Result<(), regex::Error> {
+        let mut rules = Self::new(config)?;
+        rules.runtime_secrets = std::mem::take(&mut self.runtime_secrets);
+        *self = rules;
+        Ok(())
+    }
+
+    /// Adds every string in `table` under a key matching one of the redaction keys as a secret
+    pub fn add_config_secrets(&mut self, table: &toml::Table) {
+        let mut secrets = Vec::new();
+        collect_config_secrets(self, table, &mut secrets);
+        for secret in secrets {
+            self.add_secret(&secret);
+        }
+    }
+
+    /// Whether a config value under `key` should be treated as a secret
+    fn is_secret_key(&self, key: &str) -> bool {
+        let key = key.to_lowercase();
+        self.key_names
+            .iter()
+            .any(|name| key.contains(name.as_str()))
+    }
+
+    /// Returns `text` with every rule applied, or as is if redaction is disabled
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        if !self.enabled {
+            return text;
+        }
+        // Longest first, so a secret containing another one is masked as a whole
+        let mut secrets: Vec<String> = self
+            .secrets
+            .iter()
+            .chain(&self.runtime_secrets)
+            .flat_map(|secret| secret_forms(secret))
+            .collect();
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        for secret in secrets {
+            if let Some(replaced) = replace_token(&text, &secret) {
+                text = Cow::Owned(replaced);
+            }
+        }
+        if let Some(keys) = &self.keys {
+            // Quoted values keep their quotes, so JSON output stays valid
+            let replaced = keys.replace_all(&text, |captures: &Captures| {
+                let quote = if captures.get(2).is_some() {
+                    "\\\""
+                } else if captures.get(3).is_some() {
+                    "\""
+                } else if captures.get(4).is_some() {
+                    "'"
+                } else {
+                    ""
+                };
+                format!("{}{quote}{MASK}{quote}", &captures[1])
+            });
+            if let Cow::Owned(replaced) = replaced {
+                text = Cow::Owned(replaced);
+            }
+        }
+        for home in &self.home_paths {
+            text = replace(text, home, &format!("${{1}}{MASK}"));
+        }
+        for pattern in &self.patterns {
+            text = replace(text, pattern, MASK);
+        }
+        text
+    }
+}
+
+/// `secret` as is, and as it shows up inside a JSON string or Debug output if that's different
+fn secret_forms(secret: &str) -> Vec<String> {
+    let mut forms = vec![secret.to_string()];
+    if let Ok(quoted) = serde_json::to_string(secret) {
+        let escaped = &quoted[1..quoted.len() - 1];
+        if escaped != secret {
+            forms.push(escaped.to_string());
+        }
+    }
+    forms
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Replaces every `needle` in `text` that isn't part of a longer word (I.e a slot named "Al" in
+/// "Also"), or the tail of an escape sequence (I.e "n" in "\n"), None if there aren't any
+fn replace_token(text: &str, needle: &str) -> Option<String> {
+    let first = needle.chars().next()?;
+    let last = needle.chars().next_back()?;
+    let mut replaced = String::new();
+    let mut copied = 0;
+    for (start, _) in text.match_indices(needle) {
+        let end = start + needle.len();
+        let before = &text[..start];
+        let joined_before =
+            is_word_char(first) && before.chars().next_back().is_some_and(is_word_char);
+        let joined_after =
+            is_word_char(last) && text[end..].chars().next().is_some_and(is_word_char);
+        let escaped = before.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1;
+        if joined_before || joined_after || escaped {
+            continue;
+        }
+        replaced.push_str(&text[copied..start]);
+        replaced.push_str(MASK);
+        copied = end;
+    }
+    if copied == 0 {
+        return None;
+    }
+    replaced.push_str(&text[copied..]);
+    Some(replaced)
+}
+
+fn replace<'a>(text: Cow<'a, str>, regex: &Regex, replacement: &str) -> Cow<'a, str> {
+    match regex.replace_all(&text, replacement) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(replaced) => Cow::Owned(replaced),
+    }
+}
+
+static REDACTOR: LazyLock<RwLock<Redactor>> =
+    LazyLock::new(|| RwLock::new(Redactor::new(&RedactionConfig::default()).unwrap_or_default()));
+
+/// Replaces the active rules, keeping secrets added through [`add_secret`] even while redaction
+/// is disabled
+pub fn apply_redaction(config: &RedactionConfig) {
+    // The guard has to be gone before logging, since the encoder reads the rules
+    let updated = match REDACTOR.write() {
+        Ok(mut active) => active.replace_rules(config).map_err(|err| err.to_string()),
+        Err(_) => Err("PoisonError upon trying to update redaction rules".to_string()),
+    };
+    if let Err(err) = updated {
+        log::error!("Unable to update redaction rules, keeping the previous ones: {err}");
+    }
+}
+
+/// Masks `secret` everywhere from now on (I.e the room password or slot name once connected)
+pub fn add_secret(secret: &str) {
+    let added = match REDACTOR.write() {
+        Ok(mut redactor) => {
+            redactor.add_secret(secret);
+            true
+        }
+        Err(_) => false,
+    };
+    if !added {
+        log::error!("PoisonError upon trying to add a redacted value");
+    }
+}
+
+/// Masks every string in `table` under a key matching one of the redaction keys (I.e a
+/// `room_password` in a connection config)
+pub fn add_config_secrets(table: &toml::Table) {
+    let added = match REDACTOR.write() {
+        Ok(mut redactor) => {
+            redactor.add_config_secrets(table);
+            true
+        }
+        Err(_) => false,
+    };
+    if !added {
+        log::error!("PoisonError upon trying to add config secrets");
+    }
+}
+
+fn collect_config_secrets(redactor: &Redactor, table: &toml::Table, secrets: &mut Vec<String>) {
+    for (key, value) in table {
+        if redactor.is_secret_key(key) {
+            collect_strings(value, secrets);
+        } else if let toml::Value::Table(inner) = value {
+            collect_config_secrets(redactor, inner, secrets);
+        } else if let toml::Value::Array(values) = value {
+            for value in values {
+                if let toml::Value::Table(inner) = value {
+                    collect_config_secrets(redactor, inner, secrets);
+                }
+            }
+        }
+    }
+}
+
+fn collect_strings(value: &toml::Value, secrets: &mut Vec<String>) {
+    match value {
+        toml::Value::String(secret) => secrets.push(secret.clone()),
+        toml::Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_strings(value, secrets)),
+        toml::Value::Table(table) => table
+            .values()
+            .for_each(|value| collect_strings(value, secrets)),
+        _ => {}
+    }
+}
+
+/// Applies the active rules to `text`, for anything that leaves the game besides the log itself
+pub fn redact(text: &str) -> String {
+    match REDACTOR.read() {
+        Ok(redactor) => redactor.redact(text).into_owned(),
+        // Better to lose the text than to leak it
+        Err(_) => MASK.to_string(),
+    }
+}
+
+/// Wraps another encoder, redacting its output before it gets written
+#[derive(Debug)]
+pub struct RedactingEncoder {
+    inner: Box<dyn Encode>,
+    /// Used instead of the global rules when set
+    rules: Option<Redactor>,
+}
+
+impl RedactingEncoder {
+    /// Redacts with the global rules, so it follows [`apply_redaction`] and [`add_secret`]
+    pub fn new(inner: Box<dyn Encode>) -> Self {
+        Self { inner, rules: None }
+    }
+
+    /// Redacts with a fixed set of rules instead of the global ones
+    pub fn with_rules(inner: Box<dyn Encode>, rules: Redactor) -> Self {
+        Self {
+            inner,
+            rules: Some(rules),
+        }
+    }
+}
+
+impl Encode for RedactingEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let mut buffer = SimpleWriter(Vec::new());
+        self.inner.encode(&mut buffer, record)?;
+        let line = String::from_utf8_lossy(&buffer.0);
+        match &self.rules {
+            Some(rules) => w.write_all(rules.redact(&line).as_bytes())?,
+            None => w.write_all(redact(&line).as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::json::JsonLinesEncoder;
+    use log::Level;
+    use log4rs::append::Append;
+    use log4rs::append::file::FileAppender;
+    use log4rs::encode::pattern::PatternEncoder;
+    use std::fs;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionConfig::default()).unwrap()
+    }
+
+    fn append(appender: &dyn Append, message: &str) {
+        appender
+            .append(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .target("redaction_test")
+                    .build(),
+            )
+            .unwrap();
+        appender.flush();
+    }
+
+    fn written_through(
+        encoder: Box<dyn Encode>,
+        rules: Redactor,
+        name: &str,
+        messages: &[&str],
+    ) -> String {
+        let path = std::env::temp_dir().join(format!(
+            "redaction_test_{}_{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let appender = FileAppender::builder()
+            .encoder(Box::new(RedactingEncoder::with_rules(encoder, rules)))
+            .append(false)
+            .build(&path)
+            .unwrap();
+        for message in messages {
+            append(&appender, message);
+        }
+        drop(appender);
+        let written = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        written
+    }
+
+    #[test]
+    fn masks_debug_option_values() {
+        let redacted = redactor()
+            .redact(r#"Connection { password: Some("hunter2"), port: 38281 }"#)
+            .into_owned();
+        assert!(!redacted.contains("hunter2"), "{redacted}");
+        assert!(
+            redacted.contains(r#"password: Some("[REDACTED]")"#),
+            "{redacted}"
+        );
+        assert!(redacted.contains("port: 38281"));
+    }
+
+    #[test]
+    fn masks_quoted_values_with_spaces() {
+        let redactor = redactor();
+        for text in [
+            r#"password = "correct horse battery""#,
+            "password: 'correct horse battery'",
+            r#"{"message":"password: \"correct horse battery\""}"#,
+        ] {
+            let redacted = redactor.redact(text);
+            assert!(!redacted.contains("horse"), "{redacted}");
+        }
+    }
+
+    #[test]
+    fn keeps_json_valid() {
+        let redacted = redactor()
+            .redact(r#"{"password":"hunter2","token": "abc def","level":"INFO"}"#)
+            .into_owned();
+        let value: serde_json::Value = serde_json::from_str(&redacted).unwrap();
+        assert_eq!(value["password"], MASK);
+        assert_eq!(value["token"], MASK);
+        assert_eq!(value["level"], "INFO");
+    }
+
+    #[test]
+    fn masks_home_paths() {
+        let redacted = redactor()
+            .redact(r"C:\Users\someone\AppData and /home/someone/.local")
+            .into_owned();
+        assert!(!redacted.contains("someone"), "{redacted}");
+    }
+
+    #[test]
+    fn runtime_secrets_survive_disabling() {
+        let mut redactor = redactor();
+        redactor.add_secret("runtime-secret-7f3a");
+        redactor
+            .replace_rules(&RedactionConfig {
+                enabled: false,
+                ..RedactionConfig::default()
+            })
+            .unwrap();
+        assert_eq!(
+            redactor.redact("runtime-secret-7f3a"),
+            "runtime-secret-7f3a"
+        );
+        redactor.replace_rules(&RedactionConfig::default()).unwrap();
+        assert_eq!(redactor.redact("runtime-secret-7f3a"), MASK);
+    }
+
+    #[test]
+    fn config_secrets_are_registered() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            [connection]
+            address = "archipelago.gg:38281"
+            room_password = "config-secret-91c2"
+            "#,
+        )
+        .unwrap();
+        let mut redactor = redactor();
+        redactor.add_config_secrets(&table);
+        assert_eq!(
+            redactor.redact("joined with config-secret-91c2"),
+            "joined with [REDACTED]"
+        );
+        assert!(
+            redactor
+                .redact("archipelago.gg:38281")
+                .contains("archipelago.gg")
+        );
+    }
+
+    #[test]
+    fn secrets_only_match_whole_tokens() {
+        let mut redactor = redactor();
+        redactor.add_secret("Al");
+        redactor.add_secret("n");
+        assert_eq!(
+            redactor.redact("Al joined. Also Alpha, Sal and Al_2 didn't"),
+            "[REDACTED] joined. Also Alpha, Sal and Al_2 didn't"
+        );
+        let line = r#"{"message":"line\none n"}"#;
+        let redacted = redactor.redact(line);
+        let value: serde_json::Value = serde_json::from_str(&redacted).unwrap();
+        assert_eq!(value["message"], "line\none [REDACTED]");
+    }
+
+    #[test]
+    fn secrets_are_masked_in_their_escaped_form() {
+        let mut redactor = redactor();
+        let secret = r#"pa"ss\word"#;
+        redactor.add_secret(secret);
+        let line = serde_json::json!({ "message": format!("joined with {secret}") }).to_string();
+        let redacted = redactor.redact(&line);
+        assert!(!redacted.contains("word"), "{redacted}");
+        let value: serde_json::Value = serde_json::from_str(&redacted).unwrap();
+        assert_eq!(value["message"], "joined with [REDACTED]");
+    }
+
+    #[test]
+    fn secrets_never_reach_the_log_file() {
+        let messages = [
+            "Connecting to secret-host.example:38281 as SecretSlot",
+            r#"Connect { password: Some("room pass"), slot: "SecretSlot" }"#,
+            "token=abc123",
+        ];
+        for (name, encoder) in [
+            (
+                "text",
+                Box::new(PatternEncoder::new("{l} {t} - {m}{n}")) as Box<dyn Encode>,
+            ),
+            ("json", Box::new(JsonLinesEncoder)),
+        ] {
+            let mut rules = redactor();
+            for secret in ["secret-host.example:38281", "SecretSlot", "room pass"] {
+                rules.add_secret(secret);
+            }
+            let written = written_through(encoder, rules, name, &messages);
+            assert_eq!(written.lines().count(), messages.len(), "{written}");
+            for secret in ["secret-host", "SecretSlot", "room pass", "abc123"] {
+                assert!(!written.contains(secret), "{name}: {written}");
+            }
+            if name == "json" {
+                for line in written.lines() {
+                    serde_json::from_str::<serde_json::Value>(line).unwrap();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Keeps the last few records in memory, so crash reports and in-game panels can show recent
+//! output without going through the log file.
+//!
+//! The buffer lives outside of the appender, so it is kept when the logger gets set up again and
+//! the file is rolled over.
+use crate::logging::redaction::redact;
+use crate::logging::target_in_module;
+use log::{Level, LevelFilter, Record};
+use log4rs::append::Append;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    /// Increases by one for every record, used to only fetch what is new
+    pub id: u64,
+    pub timestamp: String,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+struct RingBuffer {
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    next_id: u64,
+}
+
+impl RingBuffer {
+    fn push(&mut self, entry: LogEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+static RING_BUFFER: LazyLock<Mutex<RingBuffer>> = LazyLock::new(|| {
+    Mutex::new(RingBuffer {
+        entries: VecDeque::new(),
+        capacity: 0,
+        next_id: 0,
+    })
+});
+
+/// Which records [`query_logs`] returns, leaving a field empty matches everything
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    /// Only records at or above this level
+    pub level: Option<LevelFilter>,
+    /// Only records from this module or its submodules (I.e "randomizer_utilities::item_sync")
+    pub target: Option<String>,
+    /// Only records newer than this id
+    pub after: Option<u64>,
+    /// At most this many of the newest matching records
+    pub limit: Option<usize>,
+}
+
+impl LogQuery {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.level.is_none_or(|level| entry.level <= level)
+            && self
+                .target
+                .as_ref()
+                .is_none_or(|target| target_in_module(&entry.target, target))
+            && self.after.is_none_or(|after| entry.id > after)
+    }
+}
+
+fn collect(buffer: &RingBuffer, query: &LogQuery) -> Vec<LogEntry> {
+    let mut entries: Vec<LogEntry> = buffer
+        .entries
+        .iter()
+        .rev()
+        .filter(|entry| query.matches(entry))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+    entries.reverse();
+    entries
+}
+
+/// Returns the buffered records matching `query`, oldest first
+pub fn query_logs(query: &LogQuery) -> Vec<LogEntry> {
+    match RING_BUFFER.lock() {
+        Ok(buffer) => collect(&buffer, query),
+        Err(err) => {
+            log::error!("PoisonError upon trying to query logs {:?}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Like [`query_logs`] but never blocks, for the crash path where the thread holding the lock
+/// might be the one that crashed
+pub fn try_query_logs(query: &LogQuery) -> Option<Vec<LogEntry>> {
+    let buffer = match RING_BUFFER.try_lock() {
+        Ok(buffer) => buffer,
+        Err(std::sync::TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        Err(std::sync::TryLockError::WouldBlock) => return None,
+    };
+    Some(collect(&buffer, query))
+}
+
+/// Sets how many records are kept, 0 stops buffering entirely
+pub fn set_capacity(capacity: usize) {
+    match RING_BUFFER.lock() {
+        Ok(mut buffer) => {
+            buffer.capacity = capacity;
+            while buffer.entries.len() > capacity {
+                buffer.entries.pop_front();
+            }
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to resize log buffer {:?}", err);
+        }
+    }
+}
+
+/// Appender that stores records in the in-memory buffer
+#[derive(Debug)]
+pub struct RingBufferAppender;
+
+impl Append for RingBufferAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        // Can't log the error from inside an appender, so a poisoned buffer just drops records
+        if let Ok(mut buffer) = RING_BUFFER.lock() {
+            let id = buffer.next_id;
+            buffer.next_id += 1;
+            buffer.push(LogEntry {
+                id,
+                timestamp: chrono::Local::now().to_rfc3339(),
+                level: record.level(),
+                target: record.target().to_string(),
+                message: redact(&record.args().to_string()),
+            });
+        }
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(capacity: usize, records: &[(Level, &str)]) -> RingBuffer {
+        let mut buffer = RingBuffer {
+            entries: VecDeque::new(),
+            capacity,
+            next_id: 0,
+        };
+        for (id, (level, target)) in records.iter().enumerate() {
+            buffer.push(LogEntry {
+                id: id as u64,
+                timestamp: String::new(),
+                level: *level,
+                target: target.to_string(),
+                message: format!("record {id}"),
+            });
+        }
+        buffer
+    }
+
+    fn ids(entries: &[LogEntry]) -> Vec<u64> {
+        entries.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn oldest_records_are_evicted() {
+        let records = [(Level::Info, "game"); 5];
+        let buffer = filled(3, &records);
+        assert_eq!(ids(&collect(&buffer, &LogQuery::default())), [2, 3, 4]);
+        let newest = LogQuery {
+            limit: Some(2),
+            ..LogQuery::default()
+        };
+        assert_eq!(ids(&collect(&buffer, &newest)), [3, 4]);
+        assert!(filled(0, &records).entries.is_empty());
+    }
+
+    #[test]
+    fn filters_by_level_and_id() {
+        let buffer = filled(
+            8,
+            &[
+                (Level::Error, "game"),
+                (Level::Debug, "game"),
+                (Level::Warn, "game"),
+                (Level::Trace, "game"),
+            ],
+        );
+        let warnings = LogQuery {
+            level: Some(LevelFilter::Warn),
+            ..LogQuery::default()
+        };
+        assert_eq!(ids(&collect(&buffer, &warnings)), [0, 2]);
+        let newer = LogQuery {
+            after: Some(1),
+            ..LogQuery::default()
+        };
+        assert_eq!(ids(&collect(&buffer, &newer)), [2, 3]);
+    }
+
+    #[test]
+    fn target_filter_stops_at_module_boundaries() {
+        let buffer = filled(
+            8,
+            &[
+                (Level::Info, "randomizer_utilities::item_sync"),
+                (Level::Info, "randomizer_utilities::item_sync::offline"),
+                (Level::Info, "randomizer_utilities::item_sync_extra"),
+                (Level::Info, "randomizer_utilities"),
+            ],
+        );
+        let query = LogQuery {
+            target: Some("randomizer_utilities::item_sync".to_string()),
+            ..LogQuery::default()
+        };
+        assert_eq!(ids(&collect(&buffer, &query)), [0, 1]);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::memory::{MemoryAccessor, MemoryAccessorExt, MemoryError, Region};
+use crate::platform;
+use crate::platform::LoadedModule;
+
+/// The memory of the process we're injected into
+///
+/// Every read and write checks the regions it touches first, so a bad address is an error rather
+/// than an access violation. [`crate::read_data_from_address`] is still there for unchecked reads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LiveMemory;
+
+impl LiveMemory {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl MemoryAccessor for LiveMemory {
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> Result<(), MemoryError> {
+        self.validate_read(address, buffer.len(), 1)?;
+        // Safety: every byte was just checked to be in a committed readable region
+        unsafe {
+            std::ptr::copy_nonoverlapping(address as *const u8, buffer.as_mut_ptr(), buffer.len());
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+        self.validate_write(address, bytes.len())?;
+        // Safety: every byte was just checked to be in a committed writable region
+        unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len());
+        }
+        Ok(())
+    }
+
+    fn write_protected(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+        platform::modify_protected_range(address, bytes.len(), || unsafe {
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len());
+        })
+        .map_err(|err| MemoryError::Os(err.to_string()))
+    }
+
+    fn unprotect(
+        &self,
+        address: usize,
+        length: usize,
+        f: &mut dyn FnMut(),
+    ) -> Result<(), MemoryError> {
+        platform::modify_protected_range(address, length, f)
+            .map_err(|err| MemoryError::Os(err.to_string()))
+    }
+
+    fn query(&self, address: usize) -> Option<Region> {
+        platform::query_region(address)
+    }
+
+    fn module_at(&self, address: usize) -> Option<LoadedModule> {
+        platform::module_at(address)
+    }
+}
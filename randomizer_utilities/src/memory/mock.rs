@@ -0,0 +1,229 @@
+use crate::memory::{MemoryAccessor, MemoryError, Protection, Region};
+use crate::platform::LoadedModule;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+#[derive(Debug)]
+struct Page {
+    bytes: Box<[u8; PAGE_SIZE]>,
+    protection: Protection,
+}
+
+/// A sparse fake address space, only the pages that were mapped exist
+///
+/// Accesses check page protections like the real thing would, and fail on the first page that
+/// doesn't allow them without touching any of the others.
+#[derive(Debug, Default)]
+pub struct MockMemory {
+    // Keyed by page base
+    pages: Mutex<BTreeMap<usize, Page>>,
+    modules: Mutex<Vec<LoadedModule>>,
+}
+
+fn page_base(address: usize) -> usize {
+    address & !(PAGE_SIZE - 1)
+}
+
+/// Each page touched by `length` bytes at `address`, as (page base, offset into the page, length)
+///
+/// None if the range runs past the end of the address space.
+fn spans(address: usize, length: usize) -> Option<impl Iterator<Item = (usize, usize, usize)>> {
+    let end = address.checked_add(length)?;
+    let mut current = address;
+    Some(std::iter::from_fn(move || {
+        if current >= end {
+            return None;
+        }
+        let base = page_base(current);
+        let offset = current - base;
+        let span = (PAGE_SIZE - offset).min(end - current);
+        current += span;
+        Some((base, offset, span))
+    }))
+}
+
+fn wrapping_range(address: usize) -> MemoryError {
+    MemoryError::InvalidAddress {
+        addr: address,
+        module: None,
+    }
+}
+
+impl MockMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps zeroed pages covering `length` bytes at `address`, existing pages keep their contents
+    /// but get `protection`
+    pub fn map(&self, address: usize, length: usize, protection: Protection) {
+        let spans = spans(address, length).expect("Mapping wraps around the address space");
+        let mut pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        for (base, _, _) in spans {
+            pages
+                .entry(base)
+                .and_modify(|page| page.protection = protection)
+                .or_insert_with(|| Page {
+                    bytes: Box::new([0; PAGE_SIZE]),
+                    protection,
+                });
+        }
+    }
+
+    /// Maps `bytes` at `address` (I.e a fake module image)
+    pub fn load(&self, address: usize, bytes: &[u8], protection: Protection) {
+        self.map(address, bytes.len(), protection);
+        self.copy_in(address, bytes);
+    }
+
+    /// Registers a module image, the pages it covers still have to be mapped separately
+    pub fn add_module(&self, name: &str, base: usize, size: usize) {
+        let mut modules = self.modules.lock().unwrap_or_else(|err| err.into_inner());
+        modules.push(LoadedModule {
+            name: name.to_string(),
+            base,
+            size,
+        });
+    }
+
+    /// Changes the protection of already mapped pages, returns false if any of them weren't
+    pub fn set_protection(&self, address: usize, length: usize, protection: Protection) -> bool {
+        let Some(spans) = spans(address, length) else {
+            return false;
+        };
+        let mut pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        let mut all_mapped = true;
+        for (base, _, _) in spans {
+            match pages.get_mut(&base) {
+                Some(page) => page.protection = protection,
+                None => all_mapped = false,
+            }
+        }
+        all_mapped
+    }
+
+    pub fn protection(&self, address: usize) -> Option<Protection> {
+        let pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        pages.get(&page_base(address)).map(|page| page.protection)
+    }
+
+    /// Reads bytes ignoring protections, for checking what a test wrote
+    pub fn peek(&self, address: usize, length: usize) -> Option<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        self.check(address, length, |_| true).ok()?;
+        self.copy_out(address, &mut buffer);
+        Some(buffer)
+    }
+
+    /// Makes sure every page in the range is mapped and passes `allowed`
+    fn check<F: Fn(Protection) -> bool>(
+        &self,
+        address: usize,
+        length: usize,
+        allowed: F,
+    ) -> Result<(), MemoryError> {
+        let spans = spans(address, length).ok_or_else(|| wrapping_range(address))?;
+        let pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        for (base, offset, _) in spans {
+            match pages.get(&base) {
+                None => {
+                    return Err(MemoryError::Unmapped {
+                        address: base + offset,
+                    });
+                }
+                Some(page) if !allowed(page.protection) => {
+                    return Err(MemoryError::ProtectionFault {
+                        address: base + offset,
+                        protection: page.protection,
+                    });
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_out(&self, address: usize, buffer: &mut [u8]) {
+        let pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        let mut done = 0;
+        for (base, offset, span) in spans(address, buffer.len()).into_iter().flatten() {
+            if let Some(page) = pages.get(&base) {
+                buffer[done..done + span].copy_from_slice(&page.bytes[offset..offset + span]);
+            }
+            done += span;
+        }
+    }
+
+    fn copy_in(&self, address: usize, bytes: &[u8]) {
+        let mut pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        let mut done = 0;
+        for (base, offset, span) in spans(address, bytes.len()).into_iter().flatten() {
+            if let Some(page) = pages.get_mut(&base) {
+                page.bytes[offset..offset + span].copy_from_slice(&bytes[done..done + span]);
+            }
+            done += span;
+        }
+    }
+}
+
+impl MemoryAccessor for MockMemory {
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> Result<(), MemoryError> {
+        self.check(address, buffer.len(), Protection::is_readable)?;
+        self.copy_out(address, buffer);
+        Ok(())
+    }
+
+    fn write_bytes(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+        self.check(address, bytes.len(), Protection::is_writable)?;
+        self.copy_in(address, bytes);
+        Ok(())
+    }
+
+    fn write_protected(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError> {
+        // Like VirtualProtect, this only fails on pages that aren't there
+        self.check(address, bytes.len(), |_| true)?;
+        self.copy_in(address, bytes);
+        Ok(())
+    }
+
+    fn unprotect(
+        &self,
+        address: usize,
+        length: usize,
+        f: &mut dyn FnMut(),
+    ) -> Result<(), MemoryError> {
+        self.check(address, length, |_| true)?;
+        let old: Vec<(usize, Protection)> = spans(address, length)
+            .into_iter()
+            .flatten()
+            .filter_map(|(base, _, _)| Some((base, self.protection(base)?)))
+            .collect();
+        self.set_protection(address, length, Protection::ExecuteReadWrite);
+        // Not holding the page lock, f is expected to write through the accessor
+        f();
+        for (base, protection) in old {
+            self.set_protection(base, 1, protection);
+        }
+        Ok(())
+    }
+
+    fn query(&self, address: usize) -> Option<Region> {
+        let base = page_base(address);
+        let pages = self.pages.lock().unwrap_or_else(|err| err.into_inner());
+        pages.get(&base).map(|page| Region {
+            base,
+            size: PAGE_SIZE,
+            protection: page.protection,
+        })
+    }
+
+    fn module_at(&self, address: usize) -> Option<LoadedModule> {
+        let modules = self.modules.lock().unwrap_or_else(|err| err.into_inner());
+        modules
+            .iter()
+            .find(|module| address >= module.base && address - module.base < module.size)
+            .cloned()
+    }
+}
@@ -0,0 +1,485 @@
+//! Access to game memory behind a trait, so code that reads or patches it can run against
+//! [`MockMemory`] instead of the live process.
+//!
+//! The crate wide helpers (I.e [`crate::try_read_data_from_address`]) go through [`accessor`], which is
+//! [`LiveMemory`] unless something else was set with [`set_accessor`].
+use crate::platform::LoadedModule;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::mem::MaybeUninit;
+use std::sync::{Arc, LazyLock, RwLock};
+
+mod live;
+mod mock;
+
+pub use live::LiveMemory;
+pub use mock::{MockMemory, PAGE_SIZE};
+
+/// Page protection, simplified to what matters for reading and patching
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+pub enum Protection {
+    NoAccess,
+    ReadOnly,
+    ReadWrite,
+    Execute,
+    ExecuteRead,
+    ExecuteReadWrite,
+}
+
+impl Protection {
+    pub fn is_readable(self) -> bool {
+        matches!(
+            self,
+            Protection::ReadOnly
+                | Protection::ReadWrite
+                | Protection::ExecuteRead
+                | Protection::ExecuteReadWrite
+        )
+    }
+
+    pub fn is_writable(self) -> bool {
+        matches!(self, Protection::ReadWrite | Protection::ExecuteReadWrite)
+    }
+}
+
+/// A committed run of pages sharing one protection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub base: usize,
+    pub size: usize,
+    pub protection: Protection,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryError {
+    /// Nothing is mapped at `address`
+    Unmapped { address: usize },
+    /// Mapped, but `protection` doesn't allow the access
+    ProtectionFault {
+        address: usize,
+        protection: Protection,
+    },
+    /// Failed validation before a checked read, `module` is the module `addr` is in, if any
+    InvalidAddress { addr: usize, module: Option<String> },
+    /// `addr` isn't a multiple of `alignment`
+    Misaligned { addr: usize, alignment: usize },
+    /// The OS refused (I.e `VirtualProtect` failing)
+    Os(String),
+}
+
+impl Display for MemoryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryError::Unmapped { address } => write!(f, "{:#X} is not mapped", address),
+            MemoryError::ProtectionFault {
+                address,
+                protection,
+            } => write!(f, "{:#X} is {}", address, protection),
+            MemoryError::InvalidAddress {
+                addr,
+                module: Some(module),
+            } => write!(f, "{:#X} in {} is not readable", addr, module),
+            MemoryError::InvalidAddress { addr, module: None } => {
+                write!(f, "{:#X} is not in any module or readable region", addr)
+            }
+            MemoryError::Misaligned { addr, alignment } => {
+                write!(f, "{:#X} is not aligned to {} bytes", addr, alignment)
+            }
+            MemoryError::Os(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MemoryError {}
+
+/// Byte level access to a process' memory
+pub trait MemoryAccessor: Send + Sync {
+    /// Fills `buffer` with the bytes at `address`
+    fn read_bytes(&self, address: usize, buffer: &mut [u8]) -> Result<(), MemoryError>;
+
+    /// Writes `bytes` to `address`, which has to be writable already
+    fn write_bytes(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError>;
+
+    /// Writes `bytes` to `address` regardless of its protection (I.e patching code), leaving the
+    /// protection as it was afterward
+    fn write_protected(&self, address: usize, bytes: &[u8]) -> Result<(), MemoryError>;
+
+    /// Makes `length` bytes at `address` writable while `f` runs, then restores their protection
+    fn unprotect(
+        &self,
+        address: usize,
+        length: usize,
+        f: &mut dyn FnMut(),
+    ) -> Result<(), MemoryError>;
+
+    /// The committed region containing `address`, None if nothing is committed there
+    fn query(&self, address: usize) -> Option<Region>;
+
+    /// The module whose image contains `address`
+    fn module_at(&self, address: usize) -> Option<LoadedModule>;
+}
+
+/// Plain data that can be copied to and from raw bytes
+///
+/// # Safety
+///
+/// Every bit pattern has to be a valid value and the type can't have padding (I.e integers, or a
+/// `#[repr(C)]` struct made only of those with no gaps).
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(unsafe impl Pod for $ty {})*
+    };
+}
+
+impl_pod!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+unsafe impl<T: 'static> Pod for *const T {}
+unsafe impl<T: 'static> Pod for *mut T {}
+
+/// Typed reads and writes on top of any [`MemoryAccessor`]
+pub trait MemoryAccessorExt: MemoryAccessor {
+    fn read<T: Pod>(&self, address: usize) -> Result<T, MemoryError> {
+        let mut value = MaybeUninit::<T>::zeroed();
+        let buffer = unsafe {
+            std::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>())
+        };
+        self.read_bytes(address, buffer)?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Checks that `length` bytes at `address` can be read without faulting
+    ///
+    /// Every byte of the range has to be in a committed readable region, in a module image or not.
+    fn validate_read(
+        &self,
+        address: usize,
+        length: usize,
+        alignment: usize,
+    ) -> Result<(), MemoryError> {
+        if alignment > 1 && !address.is_multiple_of(alignment) {
+            return Err(MemoryError::Misaligned {
+                addr: address,
+                alignment,
+            });
+        }
+        validate_range(self, address, length, Protection::is_readable)
+    }
+
+    /// Checks that `length` bytes at `address` can be written without faulting
+    fn validate_write(&self, address: usize, length: usize) -> Result<(), MemoryError> {
+        validate_range(self, address, length, Protection::is_writable)
+    }
+
+    /// [`MemoryAccessorExt::read`], but validated first so a bad address is an error instead of
+    /// an access violation
+    fn read_checked<T: Pod>(&self, address: usize) -> Result<T, MemoryError> {
+        self.validate_read(address, size_of::<T>(), align_of::<T>())?;
+        self.read(address)
+    }
+
+    /// [`MemoryAccessor::read_bytes`], validated first
+    fn read_bytes_checked(&self, address: usize, buffer: &mut [u8]) -> Result<(), MemoryError> {
+        self.validate_read(address, buffer.len(), 1)?;
+        self.read_bytes(address, buffer)
+    }
+
+    fn write<T: Pod>(&self, address: usize, value: T) -> Result<(), MemoryError> {
+        self.write_bytes(address, value_bytes(&value))
+    }
+
+    fn write_protected_value<T: Pod>(&self, address: usize, value: T) -> Result<(), MemoryError> {
+        self.write_protected(address, value_bytes(&value))
+    }
+}
+
+impl<M: MemoryAccessor + ?Sized> MemoryAccessorExt for M {}
+
+/// Walks the regions covering `length` bytes at `address`, each of which has to be `allowed`
+fn validate_range<M: MemoryAccessor + ?Sized>(
+    memory: &M,
+    address: usize,
+    length: usize,
+    allowed: fn(Protection) -> bool,
+) -> Result<(), MemoryError> {
+    let end = address
+        .checked_add(length)
+        .ok_or(MemoryError::InvalidAddress {
+            addr: address,
+            module: None,
+        })?;
+    // Only used to say where the address is, module images can have unreadable gaps too
+    let module = memory.module_at(address);
+    let mut current = address;
+    while current < end {
+        match memory.query(current) {
+            Some(region) if allowed(region.protection) && region.size > 0 => {
+                match region.base.checked_add(region.size) {
+                    Some(next) => current = next,
+                    // The region runs to the top of the address space, so it covers the rest
+                    None => break,
+                }
+            }
+            _ => {
+                return Err(MemoryError::InvalidAddress {
+                    addr: current,
+                    module: module.map(|module| module.name),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn value_bytes<T: Pod>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+static ACCESSOR: LazyLock<RwLock<Arc<dyn MemoryAccessor>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(LiveMemory::new())));
+
+/// The accessor game memory helpers go through
+pub fn accessor() -> Arc<dyn MemoryAccessor> {
+    match ACCESSOR.read() {
+        Ok(accessor) => accessor.clone(),
+        Err(err) => err.into_inner().clone(),
+    }
+}
+
+/// Swaps the accessor used by the game memory helpers (I.e to a [`MockMemory`] in tests)
+pub fn set_accessor(accessor: Arc<dyn MemoryAccessor>) {
+    match ACCESSOR.write() {
+        Ok(mut current) => *current = accessor,
+        Err(err) => {
+            log::error!("PoisonError upon trying to set memory accessor {:?}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE_BASE: usize = 0x1_4000_0000;
+
+    /// A module with a readable header page, an unreadable page and a writable page
+    fn memory() -> MockMemory {
+        let memory = MockMemory::new();
+        memory.add_module("game.exe", MODULE_BASE, 3 * PAGE_SIZE);
+        memory.map(MODULE_BASE, PAGE_SIZE, Protection::ReadOnly);
+        memory.map(MODULE_BASE + PAGE_SIZE, PAGE_SIZE, Protection::NoAccess);
+        memory.map(
+            MODULE_BASE + 2 * PAGE_SIZE,
+            PAGE_SIZE,
+            Protection::ReadWrite,
+        );
+        memory.load(MODULE_BASE + 0x10, &[1, 2, 3, 4], Protection::ReadOnly);
+        memory
+    }
+
+    #[test]
+    fn misaligned_reads_are_rejected() {
+        let memory = memory();
+        assert_eq!(
+            memory.read_checked::<u32>(MODULE_BASE + 0x11),
+            Err(MemoryError::Misaligned {
+                addr: MODULE_BASE + 0x11,
+                alignment: 4
+            })
+        );
+        assert_eq!(
+            memory.read_checked::<u32>(MODULE_BASE + 0x10),
+            Ok(u32::from_le_bytes([1, 2, 3, 4]))
+        );
+        // Byte reads don't care about alignment
+        let mut buffer = [0; 3];
+        memory
+            .read_bytes_checked(MODULE_BASE + 0x11, &mut buffer)
+            .unwrap();
+        assert_eq!(buffer, [2, 3, 4]);
+    }
+
+    #[test]
+    fn addresses_outside_of_modules_need_a_region() {
+        let memory = memory();
+        assert_eq!(
+            memory.read_checked::<u64>(0x1000),
+            Err(MemoryError::InvalidAddress {
+                addr: 0x1000,
+                module: None
+            })
+        );
+        memory.map(0x1000, PAGE_SIZE, Protection::ReadWrite);
+        assert_eq!(memory.read_checked::<u64>(0x1000), Ok(0));
+    }
+
+    #[test]
+    fn unreadable_pages_in_a_module_are_rejected() {
+        let memory = memory();
+        assert_eq!(
+            memory.read_checked::<u64>(MODULE_BASE + PAGE_SIZE),
+            Err(MemoryError::InvalidAddress {
+                addr: MODULE_BASE + PAGE_SIZE,
+                module: Some("game.exe".to_string())
+            })
+        );
+        assert!(
+            memory
+                .read_checked::<u64>(MODULE_BASE + 2 * PAGE_SIZE)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn reads_across_regions() {
+        let memory = memory();
+        let mut buffer = [0; 16];
+        // Readable into unreadable fails at the first unreadable byte
+        assert_eq!(
+            memory.read_bytes_checked(MODULE_BASE + PAGE_SIZE - 8, &mut buffer),
+            Err(MemoryError::InvalidAddress {
+                addr: MODULE_BASE + PAGE_SIZE,
+                module: Some("game.exe".to_string())
+            })
+        );
+        memory.set_protection(MODULE_BASE + PAGE_SIZE, PAGE_SIZE, Protection::ExecuteRead);
+        assert!(
+            memory
+                .read_bytes_checked(MODULE_BASE + PAGE_SIZE - 8, &mut buffer)
+                .is_ok()
+        );
+        // Past the end of the last mapped page
+        assert!(
+            memory
+                .read_bytes_checked(MODULE_BASE + 3 * PAGE_SIZE - 8, &mut buffer)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reads_past_the_end_of_the_address_space_fail() {
+        let memory = MockMemory::new();
+        memory.map(
+            usize::MAX - PAGE_SIZE + 1,
+            PAGE_SIZE - 1,
+            Protection::ReadWrite,
+        );
+        let mut buffer = [0; 8];
+        let expected = Err(MemoryError::InvalidAddress {
+            addr: usize::MAX - 3,
+            module: None,
+        });
+        assert_eq!(
+            memory.read_bytes_checked(usize::MAX - 3, &mut buffer),
+            expected
+        );
+        assert_eq!(memory.read_bytes(usize::MAX - 3, &mut buffer), expected);
+        assert_eq!(memory.write_bytes(usize::MAX - 3, &buffer), expected);
+    }
+
+    #[test]
+    fn reads_up_to_the_top_of_the_address_space() {
+        let memory = MockMemory::new();
+        memory.load(usize::MAX - 7, &[1, 2, 3, 4, 5, 6, 7], Protection::ReadOnly);
+        let mut buffer = [0; 7];
+        assert_eq!(
+            memory.read_bytes_checked(usize::MAX - 7, &mut buffer),
+            Ok(())
+        );
+        assert_eq!(buffer, [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn writes_need_writable_pages() {
+        let memory = memory();
+        assert_eq!(
+            memory.write::<u32>(MODULE_BASE + 0x10, 7),
+            Err(MemoryError::ProtectionFault {
+                address: MODULE_BASE + 0x10,
+                protection: Protection::ReadOnly
+            })
+        );
+        assert_eq!(
+            memory.read::<u32>(MODULE_BASE + 0x10),
+            Ok(u32::from_le_bytes([1, 2, 3, 4]))
+        );
+        memory.write::<u32>(MODULE_BASE + 2 * PAGE_SIZE, 7).unwrap();
+        assert_eq!(memory.read::<u32>(MODULE_BASE + 2 * PAGE_SIZE), Ok(7));
+        assert_eq!(
+            memory.write_bytes(0x1000, &[1]),
+            Err(MemoryError::Unmapped { address: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn protected_writes_keep_the_protection() {
+        let memory = memory();
+        memory
+            .write_protected_value::<u16>(MODULE_BASE + 0x10, 0xBEEF)
+            .unwrap();
+        assert_eq!(memory.read::<u16>(MODULE_BASE + 0x10), Ok(0xBEEF));
+        assert_eq!(
+            memory.protection(MODULE_BASE + 0x10),
+            Some(Protection::ReadOnly)
+        );
+        // Even unreadable pages can be patched, only unmapped ones fail
+        memory
+            .write_protected(MODULE_BASE + PAGE_SIZE, &[0xC3])
+            .unwrap();
+        assert_eq!(
+            memory.write_protected(0x1000, &[0xC3]),
+            Err(MemoryError::Unmapped { address: 0x1000 })
+        );
+    }
+
+    #[test]
+    fn unprotect_restores_every_page() {
+        let memory = memory();
+        let start = MODULE_BASE + PAGE_SIZE - 2;
+        let mut ran = false;
+        memory
+            .unprotect(start, 4, &mut || {
+                assert_eq!(memory.protection(start), Some(Protection::ExecuteReadWrite));
+                memory.write_bytes(start, &[9, 9, 9, 9]).unwrap();
+                ran = true;
+            })
+            .unwrap();
+        assert!(ran);
+        assert_eq!(memory.protection(start), Some(Protection::ReadOnly));
+        assert_eq!(
+            memory.protection(MODULE_BASE + PAGE_SIZE),
+            Some(Protection::NoAccess)
+        );
+        assert_eq!(memory.peek(start, 4).unwrap(), vec![9, 9, 9, 9]);
+        assert!(memory.unprotect(0x1000, 1, &mut || panic!("ran")).is_err());
+    }
+
+    #[test]
+    fn modify_protected_memory_goes_through_the_accessor() {
+        let mock = Arc::new(memory());
+        set_accessor(mock.clone());
+        let target = (MODULE_BASE + 0x10) as *mut u32;
+        let result = crate::modify_protected_memory(
+            || {
+                accessor()
+                    .write::<u32>(target as usize, 0x1234_5678)
+                    .unwrap();
+                5
+            },
+            target,
+        );
+        let unmapped = crate::modify_protected_memory(|| (), 0x1000 as *mut u32);
+        set_accessor(Arc::new(LiveMemory::new()));
+        assert_eq!(result.unwrap(), 5);
+        assert!(unmapped.is_err());
+        assert_eq!(mock.read::<u32>(MODULE_BASE + 0x10), Ok(0x1234_5678));
+        assert_eq!(
+            mock.protection(MODULE_BASE + 0x10),
+            Some(Protection::ReadOnly)
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Routes Rust panics through the same crash report path as exceptions, and keeps them from
+//! unwinding out of hooks and into game code (which is undefined behaviour and kills the game with
+//! nothing in the log).
+use crate::crash_callbacks::run_crash_callbacks;
+use crate::crash_report::{PanicReport, detected_versions, recent_log};
+use crate::exception_handler::show_upload_guidance;
+use crate::paths;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::panic::{AssertUnwindSafe, PanicHookInfo};
+use std::sync::{LazyLock, Mutex, Once};
+
+static INSTALLED: Once = Once::new();
+/// How often each [`guard_hook`] caught a panic
+static HOOK_PANICS: LazyLock<Mutex<HashMap<String, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+thread_local! {
+    /// How many [`guard_hook`] calls are running on this thread
+    static GUARD_DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// Set while running a hook that already panicked, so the backtrace is only logged once
+    static REPEAT_PANIC: Cell<bool> = const { Cell::new(false) };
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
+/// A panic [`guard_hook`] is going to catch, logged but not treated as a crash
+fn log_caught_panic(info: &PanicHookInfo) {
+    if REPEAT_PANIC.get() {
+        return;
+    }
+    log::error!(
+        "Caught panic: {} at {}",
+        payload_message(info.payload()),
+        info.location()
+            .map(|loc| format!("{}:{}:{}", loc.file(), loc.line(), loc.column()))
+            .unwrap_or_else(|| "<unknown>".to_string())
+    );
+    for line in Backtrace::force_capture().to_string().lines() {
+        log::error!("{}", line);
+    }
+}
+
+fn report_panic(info: &PanicHookInfo) {
+    let thread = std::thread::current();
+    let mut report = PanicReport::new(
+        payload_message(info.payload()),
+        info.location()
+            .map(|loc| format!("{}:{}:{}", loc.file(), loc.line(), loc.column())),
+        thread.name().unwrap_or("<unnamed>").to_string(),
+        &Backtrace::force_capture().to_string(),
+    );
+    report.versions = detected_versions();
+    report.recent_log = recent_log();
+    for line in report.summary() {
+        log::error!("{}", line);
+    }
+    log::error!("Backtrace:");
+    for line in &report.backtrace {
+        log::error!("{}", line);
+    }
+    match report.write_to(&paths::logs_dir()) {
+        Ok(path) => log::error!("Crash report written to {}", path.display()),
+        Err(err) => log::error!("Unable to write crash report: {}", err),
+    }
+    crate::safe_mode::record_crash();
+    run_crash_callbacks();
+    show_upload_guidance();
+}
+
+/// Sets a panic hook that writes a crash report, chaining to whatever hook was set before
+///
+/// Panics inside [`guard_hook`] are only logged, the game keeps running after those. Only installs
+/// once, later calls do nothing.
+pub fn install_panic_hook() {
+    INSTALLED.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            previous_hook(info);
+            if is_guarded() {
+                log_caught_panic(info);
+            } else {
+                report_panic(info);
+            }
+        }));
+    });
+}
+
+/// Like `catch_unwind`, but a panic in `f` is only logged by the hook instead of being reported as
+/// a crash
+pub(crate) fn catch_guarded<R, F: FnOnce() -> R>(f: F) -> std::thread::Result<R> {
+    GUARD_DEPTH.set(GUARD_DEPTH.get() + 1);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(f));
+    GUARD_DEPTH.set(GUARD_DEPTH.get() - 1);
+    result
+}
+
+/// Whether a panic on this thread would be caught by [`catch_guarded`]
+pub(crate) fn is_guarded() -> bool {
+    GUARD_DEPTH.get() > 0
+}
+
+fn hook_panics(name: &str) -> u64 {
+    match HOOK_PANICS.lock() {
+        Ok(panics) => panics.get(name).copied().unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+/// Counts a panic caught in hook `name`, returning how many there have been
+fn record_hook_panic(name: &str) -> u64 {
+    match HOOK_PANICS.lock() {
+        Ok(mut panics) => {
+            let count = panics.entry(name.to_string()).or_insert(0);
+            *count += 1;
+            *count
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to count a hook panic {:?}", err);
+            1
+        }
+    }
+}
+
+/// Runs `f`, returning `fallback` if it panics instead of letting the panic unwind further
+///
+/// For the bodies of hooks called by the game, the panic itself has already been logged by the
+/// hook by the time this returns. Hooks run every frame, so only the first panic of each hook gets
+/// a backtrace, later ones are just counted and logged every power of two.
+pub fn guard_hook<R, F: FnOnce() -> R>(name: &str, fallback: R, f: F) -> R {
+    let outer = REPEAT_PANIC.replace(hook_panics(name) > 0);
+    let result = catch_guarded(f);
+    REPEAT_PANIC.set(outer);
+    match result {
+        Ok(result) => result,
+        Err(_) => {
+            let count = record_hook_panic(name);
+            if count == 1 {
+                log::error!("Panic in hook \"{}\", continuing with a fallback", name);
+            } else if count.is_power_of_two() {
+                log::error!("Hook \"{}\" panicked {} times so far", name, count);
+            }
+            fallback
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_hook_returns_the_fallback_and_unwinds_its_depth() {
+        assert_eq!(guard_hook("ok", 0, || 1), 1);
+        let result = guard_hook("outer", 0, || {
+            assert_eq!(GUARD_DEPTH.get(), 1);
+            guard_hook("inner", 2, || -> i32 { panic!("inner") })
+        });
+        assert_eq!(result, 2);
+        assert_eq!(GUARD_DEPTH.get(), 0);
+    }
+
+    #[test]
+    fn only_the_first_panic_of_a_hook_gets_a_backtrace() {
+        let mut repeats = Vec::new();
+        for _ in 0..3 {
+            guard_hook("repeating", (), || {
+                repeats.push(REPEAT_PANIC.get());
+                panic!("every frame")
+            });
+        }
+        assert_eq!(repeats, [false, true, true]);
+        assert_eq!(hook_panics("repeating"), 3);
+        assert!(!REPEAT_PANIC.get());
+    }
+}
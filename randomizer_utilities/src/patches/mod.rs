@@ -0,0 +1,583 @@
+//! Named byte patches on game memory, kept track of so they can be checked, undone and listed.
+//!
+//! Every patch says what bytes it expects to find and what it replaces them with. Applying it
+//! fails if the expected bytes aren't there (I.e an unknown build), and the bytes that were
+//! there are kept so the patch can be reverted later.
+use crate::memory::{MemoryAccessor, MemoryAccessorExt, MemoryError};
+use crate::safe_mode;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// The patch manager for game memory, through [`crate::memory::accessor`]
+pub static PATCHES: LazyLock<Mutex<PatchManager>> =
+    LazyLock::new(|| Mutex::new(PatchManager::new()));
+
+/// `90 90 E8`
+pub fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Patch {
+    pub name: String,
+    /// Which subsystem the patch belongs to
+    pub owner: String,
+    pub address: usize,
+    /// What has to be at `address` before the patch is applied
+    pub original: Vec<u8>,
+    pub replacement: Vec<u8>,
+}
+
+impl Patch {
+    pub fn new(
+        name: &str,
+        owner: &str,
+        address: usize,
+        original: &[u8],
+        replacement: &[u8],
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            owner: owner.to_string(),
+            address,
+            original: original.to_vec(),
+            replacement: replacement.to_vec(),
+        }
+    }
+
+    /// None if the patch runs past the end of the address space
+    pub fn end(&self) -> Option<usize> {
+        self.address.checked_add(self.replacement.len())
+    }
+
+    pub fn overlaps(&self, other: &Patch) -> bool {
+        // Patches that wrap are rejected on registration, they'd reach the end of memory anyway
+        self.address < other.end().unwrap_or(usize::MAX)
+            && other.address < self.end().unwrap_or(usize::MAX)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchError {
+    DuplicateName(String),
+    /// `name` would overlap `other`, which belongs to `other_owner`
+    Overlap {
+        name: String,
+        other: String,
+        other_owner: String,
+    },
+    /// The original and replacement bytes aren't the same length
+    LengthMismatch(String),
+    /// The patch runs past the end of the address space
+    AddressOverflow(String),
+    /// The bytes at the patch address aren't the ones it expects
+    OriginalMismatch {
+        name: String,
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    UnknownPatch(String),
+    SafeMode(String),
+    Memory {
+        name: String,
+        error: MemoryError,
+    },
+}
+
+impl Display for PatchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchError::DuplicateName(name) => write!(f, "A patch named {} already exists", name),
+            PatchError::Overlap {
+                name,
+                other,
+                other_owner,
+            } => write!(
+                f,
+                "Patch {} overlaps {} (owned by {})",
+                name, other, other_owner
+            ),
+            PatchError::LengthMismatch(name) => write!(
+                f,
+                "Patch {} has different original and replacement lengths",
+                name
+            ),
+            PatchError::AddressOverflow(name) => {
+                write!(f, "Patch {} runs past the end of the address space", name)
+            }
+            PatchError::OriginalMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Patch {} expected [{}] but found [{}]",
+                name,
+                format_bytes(expected),
+                format_bytes(found)
+            ),
+            PatchError::UnknownPatch(name) => write!(f, "No patch named {}", name),
+            PatchError::SafeMode(name) => write!(f, "Not applying {} in safe mode", name),
+            PatchError::Memory { name, error } => write!(f, "Patch {}: {}", name, error),
+        }
+    }
+}
+
+impl Error for PatchError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, strum_macros::Display)]
+pub enum PatchState {
+    Registered,
+    Applied,
+    Reverted,
+    /// The last apply or revert failed
+    Failed,
+}
+
+/// One line of [`PatchManager::status`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchStatus {
+    pub name: String,
+    pub owner: String,
+    #[serde(serialize_with = "crate::crash_report::hex_usize")]
+    pub address: usize,
+    pub length: usize,
+    pub state: PatchState,
+    /// Why the last apply or revert failed
+    pub error: Option<String>,
+}
+
+impl Display for PatchStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) at {:#X}, {} bytes: {}",
+            self.name, self.owner, self.address, self.length, self.state
+        )?;
+        if let Some(error) = &self.error {
+            write!(f, " ({})", error)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    patch: Patch,
+    state: PatchState,
+    /// What was in memory right before the patch was applied
+    saved: Option<Vec<u8>>,
+    error: Option<String>,
+}
+
+/// Keeps track of patches and what they replaced, in registration order
+pub struct PatchManager {
+    // None means the global accessor, looked up on each use
+    accessor: Option<Arc<dyn MemoryAccessor>>,
+    entries: Vec<Entry>,
+}
+
+impl Default for PatchManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PatchManager {
+    pub fn new() -> Self {
+        Self {
+            accessor: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// A manager working on `accessor` instead of the global one (I.e a
+    /// [`crate::memory::MockMemory`])
+    pub fn with_accessor(accessor: Arc<dyn MemoryAccessor>) -> Self {
+        Self {
+            accessor: Some(accessor),
+            entries: Vec::new(),
+        }
+    }
+
+    fn memory(&self) -> Arc<dyn MemoryAccessor> {
+        self.accessor
+            .clone()
+            .unwrap_or_else(crate::memory::accessor)
+    }
+
+    fn entry_mut(&mut self, name: &str) -> Result<&mut Entry, PatchError> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.patch.name == name)
+            .ok_or_else(|| PatchError::UnknownPatch(name.to_string()))
+    }
+
+    /// Adds a patch without applying it
+    ///
+    /// Overlapping patches are only allowed if they have the same owner, who then has to make sure
+    /// they aren't applied at the same time.
+    pub fn register(&mut self, patch: Patch) -> Result<(), PatchError> {
+        if patch.original.len() != patch.replacement.len() {
+            return Err(PatchError::LengthMismatch(patch.name));
+        }
+        if patch.end().is_none() {
+            return Err(PatchError::AddressOverflow(patch.name));
+        }
+        for entry in &self.entries {
+            if entry.patch.name == patch.name {
+                return Err(PatchError::DuplicateName(patch.name));
+            }
+            if entry.patch.owner != patch.owner && entry.patch.overlaps(&patch) {
+                return Err(PatchError::Overlap {
+                    name: patch.name,
+                    other: entry.patch.name.clone(),
+                    other_owner: entry.patch.owner.clone(),
+                });
+            }
+        }
+        self.entries.push(Entry {
+            patch,
+            state: PatchState::Registered,
+            saved: None,
+            error: None,
+        });
+        Ok(())
+    }
+
+    /// Reverts the patch if it's applied, then forgets it
+    pub fn unregister(&mut self, name: &str) -> Result<(), PatchError> {
+        self.revert(name)?;
+        self.entries.retain(|entry| entry.patch.name != name);
+        Ok(())
+    }
+
+    /// Checks the original bytes are in place, then writes the replacement
+    pub fn apply(&mut self, name: &str) -> Result<(), PatchError> {
+        let memory = self.memory();
+        let entry = self.entry_mut(name)?;
+        if entry.state == PatchState::Applied {
+            return Ok(());
+        }
+        let result = apply_entry(memory.as_ref(), entry);
+        entry.error = result.as_ref().err().map(PatchError::to_string);
+        entry.state = match result {
+            Ok(()) => PatchState::Applied,
+            Err(_) => PatchState::Failed,
+        };
+        result
+    }
+
+    /// Restores the bytes that were there before the patch was applied
+    pub fn revert(&mut self, name: &str) -> Result<(), PatchError> {
+        let memory = self.memory();
+        let entry = self.entry_mut(name)?;
+        let Some(saved) = entry.saved.clone() else {
+            return Ok(());
+        };
+        let patch = &entry.patch;
+        let mut current = vec![0; saved.len()];
+        if memory
+            .read_bytes_checked(patch.address, &mut current)
+            .is_ok()
+            && current != patch.replacement
+        {
+            log::warn!(
+                "{} was changed after it was applied, [{}] instead of [{}]",
+                patch.name,
+                format_bytes(&current),
+                format_bytes(&patch.replacement)
+            );
+        }
+        match memory.write_protected(patch.address, &saved) {
+            Ok(()) => {
+                entry.saved = None;
+                entry.state = PatchState::Reverted;
+                entry.error = None;
+                Ok(())
+            }
+            Err(error) => {
+                let error = PatchError::Memory {
+                    name: patch.name.clone(),
+                    error,
+                };
+                entry.state = PatchState::Failed;
+                entry.error = Some(error.to_string());
+                Err(error)
+            }
+        }
+    }
+
+    /// Applies every patch that isn't applied yet, in registration order
+    pub fn apply_all(&mut self) -> Vec<(String, Result<(), PatchError>)> {
+        let names: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| entry.patch.name.clone())
+            .collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let result = self.apply(&name);
+                (name, result)
+            })
+            .collect()
+    }
+
+    /// Reverts every applied patch, newest first so overlapping ones unwind correctly
+    pub fn revert_all(&mut self) -> Vec<(String, Result<(), PatchError>)> {
+        let names: Vec<String> = self
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.saved.is_some())
+            .map(|entry| entry.patch.name.clone())
+            .collect();
+        names
+            .into_iter()
+            .map(|name| {
+                let result = self.revert(&name);
+                (name, result)
+            })
+            .collect()
+    }
+
+    pub fn state(&self, name: &str) -> Option<PatchState> {
+        self.entries
+            .iter()
+            .find(|entry| entry.patch.name == name)
+            .map(|entry| entry.state)
+    }
+
+    pub fn status(&self) -> Vec<PatchStatus> {
+        self.entries
+            .iter()
+            .map(|entry| PatchStatus {
+                name: entry.patch.name.clone(),
+                owner: entry.patch.owner.clone(),
+                address: entry.patch.address,
+                length: entry.patch.replacement.len(),
+                state: entry.state,
+                error: entry.error.clone(),
+            })
+            .collect()
+    }
+
+    pub fn log_status(&self) {
+        log::info!("{} patches registered", self.entries.len());
+        for status in self.status() {
+            log::info!("    {}", status);
+        }
+    }
+}
+
+fn apply_entry(memory: &dyn MemoryAccessor, entry: &mut Entry) -> Result<(), PatchError> {
+    let patch = &entry.patch;
+    if safe_mode::is_safe_mode() {
+        return Err(PatchError::SafeMode(patch.name.clone()));
+    }
+    let memory_error = |error| PatchError::Memory {
+        name: patch.name.clone(),
+        error,
+    };
+    let mut current = vec![0; patch.original.len()];
+    memory
+        .read_bytes_checked(patch.address, &mut current)
+        .map_err(memory_error)?;
+    if current != patch.original {
+        return Err(PatchError::OriginalMismatch {
+            name: patch.name.clone(),
+            expected: patch.original.clone(),
+            found: current,
+        });
+    }
+    memory
+        .write_protected(patch.address, &patch.replacement)
+        .map_err(memory_error)?;
+    entry.saved = Some(current);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{MockMemory, PAGE_SIZE, Protection};
+
+    const CODE: usize = 0x1_4000_1000;
+
+    /// A manager over one read-only code page starting with `48 8B 05 90 90 E8`
+    fn manager() -> (Arc<MockMemory>, PatchManager) {
+        let memory = Arc::new(MockMemory::new());
+        memory.map(CODE, PAGE_SIZE, Protection::ExecuteRead);
+        memory.load(
+            CODE,
+            &[0x48, 0x8B, 0x05, 0x90, 0x90, 0xE8],
+            Protection::ExecuteRead,
+        );
+        let manager = PatchManager::with_accessor(memory.clone());
+        (memory, manager)
+    }
+
+    #[test]
+    fn mismatched_original_bytes_are_not_applied() {
+        let (memory, mut manager) = manager();
+        manager
+            .register(Patch::new(
+                "nop",
+                "test",
+                CODE,
+                &[0x48, 0x8B],
+                &[0x90, 0x90],
+            ))
+            .unwrap();
+        manager
+            .register(Patch::new("wrong", "test", CODE + 3, &[0xCC], &[0xC3]))
+            .unwrap();
+        assert_eq!(
+            manager.apply("wrong"),
+            Err(PatchError::OriginalMismatch {
+                name: "wrong".to_string(),
+                expected: vec![0xCC],
+                found: vec![0x90],
+            })
+        );
+        assert_eq!(manager.state("wrong"), Some(PatchState::Failed));
+        assert_eq!(memory.peek(CODE + 3, 1).unwrap(), vec![0x90]);
+        assert_eq!(manager.apply("nop"), Ok(()));
+        assert_eq!(memory.peek(CODE, 2).unwrap(), vec![0x90, 0x90]);
+        // Writing through the protection keeps the page as it was
+        assert_eq!(memory.protection(CODE), Some(Protection::ExecuteRead));
+    }
+
+    #[test]
+    fn revert_restores_the_original_bytes() {
+        let (memory, mut manager) = manager();
+        manager
+            .register(Patch::new("ret", "test", CODE + 5, &[0xE8], &[0xC3]))
+            .unwrap();
+        manager.apply("ret").unwrap();
+        assert_eq!(memory.peek(CODE + 5, 1).unwrap(), vec![0xC3]);
+        manager.revert("ret").unwrap();
+        assert_eq!(memory.peek(CODE + 5, 1).unwrap(), vec![0xE8]);
+        assert_eq!(manager.state("ret"), Some(PatchState::Reverted));
+        // Reverting twice doesn't write anything
+        assert_eq!(manager.revert("ret"), Ok(()));
+    }
+
+    #[test]
+    fn revert_all_unwinds_newest_first() {
+        let (memory, mut manager) = manager();
+        // Same owner, so they're allowed to overlap
+        manager
+            .register(Patch::new(
+                "first",
+                "test",
+                CODE,
+                &[0x48, 0x8B],
+                &[0xEB, 0x01],
+            ))
+            .unwrap();
+        manager
+            .register(Patch::new("second", "test", CODE + 1, &[0x01], &[0x02]))
+            .unwrap();
+        manager.apply("first").unwrap();
+        manager.apply("second").unwrap();
+        assert_eq!(memory.peek(CODE, 2).unwrap(), vec![0xEB, 0x02]);
+        let names: Vec<String> = manager
+            .revert_all()
+            .into_iter()
+            .map(|(name, result)| {
+                result.unwrap();
+                name
+            })
+            .collect();
+        assert_eq!(names, vec!["second", "first"]);
+        assert_eq!(memory.peek(CODE, 2).unwrap(), vec![0x48, 0x8B]);
+    }
+
+    #[test]
+    fn overlapping_patches_from_other_owners_are_rejected() {
+        let (_, mut manager) = manager();
+        manager
+            .register(Patch::new(
+                "mine",
+                "overlay",
+                CODE,
+                &[0x48, 0x8B],
+                &[0x90, 0x90],
+            ))
+            .unwrap();
+        assert_eq!(
+            manager.register(Patch::new("theirs", "ddmk", CODE + 1, &[0x8B], &[0x90])),
+            Err(PatchError::Overlap {
+                name: "theirs".to_string(),
+                other: "mine".to_string(),
+                other_owner: "overlay".to_string(),
+            })
+        );
+        assert_eq!(
+            manager.register(Patch::new("mine", "overlay", CODE + 4, &[0x90], &[0xCC])),
+            Err(PatchError::DuplicateName("mine".to_string()))
+        );
+        assert!(
+            manager
+                .register(Patch::new("next", "ddmk", CODE + 2, &[0x05], &[0x0D]))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn patches_past_the_end_of_memory_are_rejected() {
+        let (_, mut manager) = manager();
+        assert_eq!(
+            manager.register(Patch::new("wrap", "test", usize::MAX, &[0, 0], &[1, 1])),
+            Err(PatchError::AddressOverflow("wrap".to_string()))
+        );
+        assert_eq!(
+            manager.register(Patch::new("short", "test", CODE, &[0x48], &[])),
+            Err(PatchError::LengthMismatch("short".to_string()))
+        );
+    }
+
+    #[test]
+    fn status_lists_every_patch_in_order() {
+        let (_, mut manager) = manager();
+        manager
+            .register(Patch::new(
+                "nop",
+                "test",
+                CODE,
+                &[0x48, 0x8B],
+                &[0x90, 0x90],
+            ))
+            .unwrap();
+        manager
+            .register(Patch::new("wrong", "test", CODE + 3, &[0xCC], &[0xC3]))
+            .unwrap();
+        manager.apply("nop").unwrap();
+        let _ = manager.apply("wrong");
+        let status = manager.status();
+        assert_eq!(status.len(), 2);
+        assert_eq!(status[0].name, "nop");
+        assert_eq!(status[0].state, PatchState::Applied);
+        assert_eq!(status[0].length, 2);
+        assert_eq!(
+            status[0].to_string(),
+            format!("nop (test) at {:#X}, 2 bytes: Applied", CODE)
+        );
+        assert_eq!(status[1].state, PatchState::Failed);
+        assert!(
+            status[1]
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("expected [CC]")
+        );
+    }
+}
@@ -0,0 +1,254 @@
+//! Resolves the root directory that `archipelago/` and `logs/` live under, so nothing depends on
+//! the working directory the game was launched from.
+//!
+//! The root is picked from, in order:
+//! 1. The `APRANDO_DATA_DIR` environment variable
+//! 2. `data_dir` in `aprando.toml` next to the injected module
+//! 3. The game's directory, if `portable = true` is set in `aprando.toml`
+//! 4. The directory of the injected module
+//! 5. The working directory, if none of the above could be found
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const DATA_DIR_VAR: &str = "APRANDO_DATA_DIR";
+const BOOTSTRAP_FILE: &str = "aprando.toml";
+
+static DATA_ROOT: OnceLock<(PathBuf, DataRootSource)> = OnceLock::new();
+/// Why `aprando.toml` couldn't be used, kept until [`log_data_root`] can report it
+static BOOTSTRAP_ERROR: OnceLock<String> = OnceLock::new();
+
+/// Where the data root came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display)]
+pub enum DataRootSource {
+    Environment,
+    Config,
+    Portable,
+    ModuleDirectory,
+    WorkingDirectory,
+    Manual,
+}
+
+/// Read from `aprando.toml`, which sits next to the injected module rather than in the data root
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BootstrapConfig {
+    pub data_dir: Option<PathBuf>,
+    pub portable: bool,
+}
+
+fn module_directory() -> Option<PathBuf> {
+    crate::platform::current_module_path()?
+        .parent()
+        .map(Path::to_path_buf)
+}
+
+fn game_directory() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()?
+        .parent()
+        .map(Path::to_path_buf)
+}
+
+fn read_bootstrap(module_dir: &Path) -> Result<BootstrapConfig, String> {
+    let path = module_dir.join(BOOTSTRAP_FILE);
+    match fs::read_to_string(&path) {
+        Ok(contents) => toml::from_str(&contents)
+            .map_err(|err| format!("Unable to parse {}: {}", path.display(), err)),
+        Err(_) => Ok(BootstrapConfig::default()),
+    }
+}
+
+/// Picks the data root from the given inputs, kept separate from the environment so it can be
+/// reasoned about on its own
+pub fn resolve_data_root(
+    env_value: Option<PathBuf>,
+    bootstrap: &BootstrapConfig,
+    module_dir: Option<&Path>,
+    game_dir: Option<&Path>,
+) -> (PathBuf, DataRootSource) {
+    if let Some(dir) = env_value.filter(|dir| !dir.as_os_str().is_empty()) {
+        return (dir, DataRootSource::Environment);
+    }
+    if let Some(dir) = &bootstrap.data_dir {
+        // Relative paths are taken as relative to the bootstrap file
+        let dir = match module_dir {
+            Some(module_dir) if dir.is_relative() => module_dir.join(dir),
+            _ => dir.clone(),
+        };
+        return (dir, DataRootSource::Config);
+    }
+    if bootstrap.portable
+        && let Some(game_dir) = game_dir
+    {
+        return (game_dir.to_path_buf(), DataRootSource::Portable);
+    }
+    match module_dir {
+        Some(module_dir) => (module_dir.to_path_buf(), DataRootSource::ModuleDirectory),
+        None => (PathBuf::from("."), DataRootSource::WorkingDirectory),
+    }
+}
+
+// Doesn't log, the logger itself needs the data root for its file
+fn data_root_entry() -> &'static (PathBuf, DataRootSource) {
+    DATA_ROOT.get_or_init(|| {
+        let module_dir = module_directory();
+        let bootstrap = match module_dir.as_deref().map(read_bootstrap) {
+            Some(Ok(bootstrap)) => bootstrap,
+            Some(Err(err)) => {
+                let _ = BOOTSTRAP_ERROR.set(err);
+                BootstrapConfig::default()
+            }
+            None => BootstrapConfig::default(),
+        };
+        resolve_data_root(
+            std::env::var_os(DATA_DIR_VAR).map(PathBuf::from),
+            &bootstrap,
+            module_dir.as_deref(),
+            game_directory().as_deref(),
+        )
+    })
+}
+
+/// Logs the data root and where it came from, once the logger is up
+pub fn log_data_root() {
+    let (root, source) = data_root_entry();
+    if let Some(err) = BOOTSTRAP_ERROR.get() {
+        log::warn!("{}", err);
+    }
+    log::debug!("Data root: {} ({})", root.display(), source);
+}
+
+/// Overrides the data root, must be called before anything touches the data directory
+pub fn set_data_root(path: PathBuf) -> Result<(), PathBuf> {
+    DATA_ROOT
+        .set((path, DataRootSource::Manual))
+        .map_err(|(path, _)| path)
+}
+
+pub fn data_root() -> &'static Path {
+    &data_root_entry().0
+}
+
+pub fn data_root_source() -> DataRootSource {
+    data_root_entry().1
+}
+
+/// `<data root>/archipelago`, where configs and per-room data are kept
+pub fn archipelago_dir() -> PathBuf {
+    data_root().join("archipelago")
+}
+
+/// `<data root>/logs`
+pub fn logs_dir() -> PathBuf {
+    data_root().join("logs")
+}
+
+/// A fresh data root under the temp directory, shared by every test in the process
+#[cfg(test)]
+pub(crate) fn test_data_root() -> &'static Path {
+    static ROOT: OnceLock<PathBuf> = OnceLock::new();
+    ROOT.get_or_init(|| {
+        let root =
+            std::env::temp_dir().join(format!("randomizer_utilities_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        if let Err(path) = set_data_root(root.clone()) {
+            assert_eq!(
+                path,
+                data_root(),
+                "data root was resolved before the tests set it"
+            );
+        }
+        root
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_the_data_root_in_order() {
+        let module = Path::new("/games/dmc3/mods");
+        let game = Path::new("/games/dmc3");
+        let config = |data_dir: Option<&str>, portable: bool| BootstrapConfig {
+            data_dir: data_dir.map(PathBuf::from),
+            portable,
+        };
+        let cases = [
+            (
+                "environment over config",
+                Some("/env"),
+                config(Some("/config"), true),
+                Some(module),
+                Some(game),
+                ("/env", DataRootSource::Environment),
+            ),
+            (
+                "empty environment is ignored",
+                Some(""),
+                config(Some("/config"), false),
+                Some(module),
+                Some(game),
+                ("/config", DataRootSource::Config),
+            ),
+            (
+                "relative data_dir joins the module dir",
+                None,
+                config(Some("data"), false),
+                Some(module),
+                Some(game),
+                ("/games/dmc3/mods/data", DataRootSource::Config),
+            ),
+            (
+                "relative data_dir without a module dir",
+                None,
+                config(Some("data"), false),
+                None,
+                Some(game),
+                ("data", DataRootSource::Config),
+            ),
+            (
+                "portable with a game dir",
+                None,
+                config(None, true),
+                Some(module),
+                Some(game),
+                ("/games/dmc3", DataRootSource::Portable),
+            ),
+            (
+                "portable without a game dir",
+                None,
+                config(None, true),
+                Some(module),
+                None,
+                ("/games/dmc3/mods", DataRootSource::ModuleDirectory),
+            ),
+            (
+                "module dir by default",
+                None,
+                config(None, false),
+                Some(module),
+                Some(game),
+                ("/games/dmc3/mods", DataRootSource::ModuleDirectory),
+            ),
+            (
+                "working dir fallback",
+                None,
+                config(None, true),
+                None,
+                None,
+                (".", DataRootSource::WorkingDirectory),
+            ),
+        ];
+        for (name, env, bootstrap, module_dir, game_dir, (root, source)) in cases {
+            assert_eq!(
+                resolve_data_root(env.map(PathBuf::from), &bootstrap, module_dir, game_dir),
+                (PathBuf::from(root), source),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! OS specific backends. Everything that has to talk to Win32 directly lives in `win32`, while
+//! `stub` stands in for it on other hosts so the rest of the crate can be built and tested there.
+
+use serde::Serialize;
+
+#[cfg(not(windows))]
+mod stub;
+#[cfg(windows)]
+mod win32;
+
+#[cfg(not(windows))]
+pub use stub::*;
+#[cfg(windows)]
+pub use win32::*;
+
+/// A module mapped into the process
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LoadedModule {
+    pub name: String,
+    #[serde(serialize_with = "crate::crash_report::hex_usize")]
+    pub base: usize,
+    #[serde(serialize_with = "crate::crash_report::hex_usize")]
+    pub size: usize,
+}
@@ -0,0 +1,59 @@
+//! Stand-in backend for non-Windows hosts. There is no game process to inspect here, so module
+//! lookups come back empty and memory protection is left alone.
+use crate::memory::Region;
+use crate::platform::LoadedModule;
+use std::error::Error;
+use std::path::PathBuf;
+
+pub fn is_library_loaded(_name: &str) -> bool {
+    false
+}
+
+/// Always returns 0, as there are no game modules outside of Windows
+pub fn get_base_address(_module_name: &str) -> usize {
+    0
+}
+
+pub fn module_from_address(_addr: usize) -> Option<(usize, String)> {
+    None
+}
+
+pub fn module_at(_addr: usize) -> Option<LoadedModule> {
+    None
+}
+
+/// Always None, nothing here is treated as game memory
+pub fn query_region(_addr: usize) -> Option<Region> {
+    None
+}
+
+/// There's no injected module here, so this is the running executable instead
+pub fn current_module_path() -> Option<PathBuf> {
+    std::env::current_exe().ok()
+}
+
+pub fn loaded_modules() -> Vec<LoadedModule> {
+    Vec::new()
+}
+
+/// Always fails, there is no game memory to read here
+pub fn read_memory(_address: usize, _buffer: &mut [u8]) -> bool {
+    false
+}
+
+/// Runs `f` directly, page protections are not touched on this platform
+pub fn modify_protected_range<F, R>(
+    _address: usize,
+    _length: usize,
+    f: F,
+) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> R,
+{
+    Ok(f())
+}
+
+/// Prints to stderr, there are no message boxes here
+pub fn show_notice(title: &str, text: &str) {
+    eprintln!("{title}: {text}");
+}
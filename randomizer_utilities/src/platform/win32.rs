@@ -0,0 +1,273 @@
+use crate::memory::{Protection, Region};
+use crate::platform::LoadedModule;
+use std::error::Error;
+use std::ffi::{OsStr, c_void};
+use std::os::windows::ffi::OsStrExt;
+use std::path::PathBuf;
+use windows::Win32::Foundation::{GetLastError, HMODULE};
+use windows::Win32::System::Diagnostics::Debug::ReadProcessMemory;
+use windows::Win32::System::LibraryLoader::{
+    GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS, GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+    GetModuleFileNameW, GetModuleHandleExW, GetModuleHandleW,
+};
+use windows::Win32::System::Memory::{
+    MEM_COMMIT, MEMORY_BASIC_INFORMATION, PAGE_EXECUTE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE,
+    PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_PROTECTION_FLAGS, PAGE_READONLY, PAGE_READWRITE,
+    PAGE_WRITECOPY, VirtualProtect, VirtualQuery,
+};
+use windows::Win32::System::ProcessStatus::{EnumProcessModules, GetModuleInformation, MODULEINFO};
+use windows::Win32::System::Threading::GetCurrentProcess;
+use windows::Win32::UI::WindowsAndMessaging::{
+    MB_ICONWARNING, MB_OK, MB_SETFOREGROUND, MessageBoxW,
+};
+use windows::core::PCWSTR;
+
+fn to_wide(name: &str) -> Vec<u16> {
+    OsStr::new(name)
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+pub fn is_library_loaded(name: &str) -> bool {
+    let wide_name = to_wide(name);
+    unsafe {
+        if let Ok(module_handle) = GetModuleHandleW(PCWSTR::from_raw(wide_name.as_ptr())) {
+            !module_handle.is_invalid()
+        } else {
+            false
+        }
+    }
+}
+
+/// Generic method to get the base address for the specified module, returns 0 if it doesn't exist
+pub fn get_base_address(module_name: &str) -> usize {
+    let wide_name = to_wide(module_name);
+    unsafe {
+        if let Ok(module_handle) = GetModuleHandleW(PCWSTR::from_raw(wide_name.as_ptr())) {
+            module_handle.0 as usize
+        } else {
+            0
+        }
+    }
+}
+
+/// Full path of the module `hmod` was loaded from
+fn module_file_path(hmod: HMODULE) -> Option<PathBuf> {
+    let mut buf = [0u16; 260]; // MAX_PATH
+    let len = unsafe { GetModuleFileNameW(Option::from(hmod), &mut buf) };
+    if len == 0 {
+        return None;
+    }
+    Some(PathBuf::from(String::from_utf16_lossy(
+        &buf[..len as usize],
+    )))
+}
+
+/// Resolve address → (module base, filename)
+pub fn module_from_address(addr: usize) -> Option<(usize, String)> {
+    let mut hmod = HMODULE::default();
+    unsafe {
+        if GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+            PCWSTR::from_raw(addr as *const u16),
+            &mut hmod,
+        )
+        .is_err()
+        {
+            return None;
+        }
+    }
+
+    let full_path = module_file_path(hmod)?;
+    let file_name = full_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("<unknown>")
+        .to_string();
+
+    Some((hmod.0 as usize, file_name))
+}
+
+/// The module whose image contains `addr`
+pub fn module_at(addr: usize) -> Option<LoadedModule> {
+    let mut hmod = HMODULE::default();
+    let mut info = MODULEINFO::default();
+    unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            PCWSTR::from_raw(addr as *const u16),
+            &mut hmod,
+        )
+        .ok()?;
+        GetModuleInformation(
+            GetCurrentProcess(),
+            hmod,
+            &mut info,
+            size_of::<MODULEINFO>() as u32,
+        )
+        .ok()?;
+    }
+    Some(LoadedModule {
+        name: module_file_path(hmod)?
+            .file_name()?
+            .to_string_lossy()
+            .to_string(),
+        base: info.lpBaseOfDll as usize,
+        size: info.SizeOfImage as usize,
+    })
+}
+
+/// The committed region containing `addr`, None if it's free or only reserved
+pub fn query_region(addr: usize) -> Option<Region> {
+    let mut info = MEMORY_BASIC_INFORMATION::default();
+    let written = unsafe {
+        VirtualQuery(
+            Some(addr as *const c_void),
+            &mut info,
+            size_of::<MEMORY_BASIC_INFORMATION>(),
+        )
+    };
+    if written == 0 || info.State != MEM_COMMIT {
+        return None;
+    }
+    // Guard pages fault on first touch, so they count as inaccessible
+    let protection = if info.Protect.0 & PAGE_GUARD.0 != 0 {
+        Protection::NoAccess
+    } else {
+        match PAGE_PROTECTION_FLAGS(info.Protect.0 & 0xFF) {
+            PAGE_READONLY => Protection::ReadOnly,
+            PAGE_READWRITE | PAGE_WRITECOPY => Protection::ReadWrite,
+            PAGE_EXECUTE => Protection::Execute,
+            PAGE_EXECUTE_READ => Protection::ExecuteRead,
+            PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY => Protection::ExecuteReadWrite,
+            _ => Protection::NoAccess,
+        }
+    };
+    Some(Region {
+        base: info.BaseAddress as usize,
+        size: info.RegionSize,
+        protection,
+    })
+}
+
+/// Path of the module this crate was compiled into (I.e the injected randomizer DLL)
+pub fn current_module_path() -> Option<PathBuf> {
+    let mut hmod = HMODULE::default();
+    unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            PCWSTR::from_raw(current_module_path as *const () as *const u16),
+            &mut hmod,
+        )
+        .ok()?;
+    }
+    module_file_path(hmod)
+}
+
+/// Every module currently loaded into the game, in load order
+pub fn loaded_modules() -> Vec<LoadedModule> {
+    let mut handles = vec![HMODULE::default(); 1024];
+    let mut needed = 0u32;
+    unsafe {
+        let process = GetCurrentProcess();
+        if EnumProcessModules(
+            process,
+            handles.as_mut_ptr(),
+            (handles.len() * size_of::<HMODULE>()) as u32,
+            &mut needed,
+        )
+        .is_err()
+        {
+            return Vec::new();
+        }
+        let count = (needed as usize / size_of::<HMODULE>()).min(handles.len());
+        handles[..count]
+            .iter()
+            .filter_map(|&hmod| {
+                let mut info = MODULEINFO::default();
+                GetModuleInformation(process, hmod, &mut info, size_of::<MODULEINFO>() as u32)
+                    .ok()?;
+                let name = module_file_path(hmod)?
+                    .file_name()?
+                    .to_string_lossy()
+                    .to_string();
+                Some(LoadedModule {
+                    name,
+                    base: info.lpBaseOfDll as usize,
+                    size: info.SizeOfImage as usize,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Copies memory at `address` into `buffer`, failing instead of faulting if it isn't readable
+pub fn read_memory(address: usize, buffer: &mut [u8]) -> bool {
+    unsafe {
+        ReadProcessMemory(
+            GetCurrentProcess(),
+            address as *const c_void,
+            buffer.as_mut_ptr() as *mut c_void,
+            buffer.len(),
+            None,
+        )
+        .is_ok()
+    }
+}
+
+/// Makes `length` bytes at `address` writable while `f` runs, then restores their protection
+pub fn modify_protected_range<F, R>(
+    address: usize,
+    length: usize,
+    f: F,
+) -> Result<R, Box<dyn Error>>
+where
+    F: FnOnce() -> R,
+{
+    let mut old_protect = PAGE_PROTECTION_FLAGS::default();
+    unsafe {
+        if VirtualProtect(
+            address as *mut c_void,
+            length,
+            PAGE_EXECUTE_READWRITE,
+            &mut old_protect,
+        )
+        .is_err()
+        {
+            return Err(format!("Failed to use VirtualProtect (1): {:?}", GetLastError()).into());
+        }
+        let res = f();
+        if VirtualProtect(
+            address as *mut c_void,
+            length,
+            old_protect,
+            &mut old_protect,
+        )
+        .is_err()
+        {
+            return Err(format!("Failed to use VirtualProtect (2): {:?}", GetLastError()).into());
+        }
+        Ok(res)
+    }
+}
+
+/// Shows `text` in a message box from its own thread, so the caller (I.e still inside
+/// `DllMain`) isn't blocked until the user closes it
+pub fn show_notice(title: &str, text: &str) {
+    let title = to_wide(title);
+    let text = to_wide(text);
+    let spawned = std::thread::Builder::new()
+        .name("notice".to_string())
+        .spawn(move || unsafe {
+            MessageBoxW(
+                None,
+                PCWSTR::from_raw(text.as_ptr()),
+                PCWSTR::from_raw(title.as_ptr()),
+                MB_OK | MB_ICONWARNING | MB_SETFOREGROUND,
+            );
+        });
+    if let Err(err) = spawned {
+        log::error!("Unable to show notice: {}", err);
+    }
+}
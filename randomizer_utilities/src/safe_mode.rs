@@ -0,0 +1,225 @@
+//! Safe mode for when our hooks crash the game on boot (I.e after a game patch or mod update).
+//!
+//! A crash counter is kept in the data directory. Crashes within [`EARLY_CRASH_WINDOW`] of startup
+//! increment it, while a clean shutdown or staying up past that window resets it. Once it reaches
+//! [`CRASH_THRESHOLD`], the next launch starts in safe mode: the overlay, DDMK hooks and byte
+//! patches are skipped so the game is still reachable and a bug report can be made.
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// Consecutive early crashes before the next launch starts in safe mode
+pub const CRASH_THRESHOLD: u32 = 3;
+/// Crashes after running this long aren't counted, and the counter is reset
+pub const EARLY_CRASH_WINDOW: Duration = Duration::from_secs(120);
+
+const COUNTER_FILE_NAME: &str = "crash_counter.json";
+const SAFE_MODE_NOTICE: &str = "The game crashed several times in a row shortly after starting, so the overlay, DDMK hooks and game patches are disabled for this run. Please create a bug report and upload it to either Github or to the Archipelago game thread";
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+static CRASH_RECORDED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrashCounter {
+    /// Early crashes since the last clean run
+    pub consecutive_crashes: u32,
+    pub last_crash: Option<String>,
+}
+
+impl CrashCounter {
+    /// Reads the counter at `path`, a missing or unreadable file counts as no crashes
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn record_crash(&mut self, timestamp: String) {
+        self.consecutive_crashes = self.consecutive_crashes.saturating_add(1);
+        self.last_crash = Some(timestamp);
+    }
+
+    pub fn should_enter_safe_mode(&self) -> bool {
+        self.consecutive_crashes >= CRASH_THRESHOLD
+    }
+}
+
+/// `<data root>/archipelago/crash_counter.json`
+pub fn counter_path() -> PathBuf {
+    paths::archipelago_dir().join(COUNTER_FILE_NAME)
+}
+
+/// Whether hooks and patches should be skipped for this run
+pub fn is_safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::SeqCst)
+}
+
+/// Reads the crash counter to decide on safe mode, then starts the stable run timer
+///
+/// Needs to run before the overlay, DDMK hooks or any patches are set up. Only the first call does
+/// anything.
+pub fn init_safe_mode() {
+    if STARTED.set(Instant::now()).is_err() {
+        return;
+    }
+    let counter = CrashCounter::load(&counter_path());
+    if counter.should_enter_safe_mode() {
+        SAFE_MODE.store(true, Ordering::SeqCst);
+        log::error!(
+            "The game crashed {} times in a row shortly after starting (last at {}), starting in safe mode",
+            counter.consecutive_crashes,
+            counter.last_crash.as_deref().unwrap_or("<unknown>")
+        );
+        log::error!("{}", SAFE_MODE_NOTICE);
+        // The overlay is one of the things that's off, so this is the only way to see it in game
+        crate::platform::show_notice("Archipelago safe mode", SAFE_MODE_NOTICE);
+    } else if counter.consecutive_crashes > 0 {
+        log::warn!(
+            "The game crashed {} time(s) in a row shortly after starting, safe mode starts after {}",
+            counter.consecutive_crashes,
+            CRASH_THRESHOLD
+        );
+    }
+    // Safety: on_exit doesn't unwind and only touches statics that outlive it
+    if unsafe { atexit(on_exit) } != 0 {
+        log::warn!("Unable to register the clean shutdown hook");
+    }
+    let spawned = std::thread::Builder::new()
+        .name("safe-mode-timer".to_string())
+        .spawn(|| {
+            std::thread::sleep(EARLY_CRASH_WINDOW);
+            if !CRASH_RECORDED.load(Ordering::SeqCst) {
+                log::debug!("Stable run, resetting crash counter");
+                reset_crash_counter();
+            }
+        });
+    if let Err(err) = spawned {
+        log::error!("Unable to start stable run timer: {}", err);
+    }
+}
+
+/// Counts a crash towards safe mode if it happened early enough, at most once per run
+pub fn record_crash() {
+    let Some(started) = STARTED.get() else {
+        return;
+    };
+    if started.elapsed() >= EARLY_CRASH_WINDOW || CRASH_RECORDED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let path = counter_path();
+    let mut counter = CrashCounter::load(&path);
+    counter.record_crash(chrono::Local::now().to_rfc3339());
+    match counter.save(&path) {
+        Ok(()) => log::info!(
+            "Early crash {} of {} before safe mode",
+            counter.consecutive_crashes,
+            CRASH_THRESHOLD
+        ),
+        Err(err) => log::error!("Unable to update crash counter: {}", err),
+    }
+}
+
+unsafe extern "C" {
+    fn atexit(callback: extern "C" fn()) -> std::ffi::c_int;
+}
+
+extern "C" fn on_exit() {
+    clean_shutdown();
+}
+
+/// Resets the counter unless this run crashed, so the next launch starts normally
+///
+/// Runs on exit once [`init_safe_mode`] was called, can also be called from `DLL_PROCESS_DETACH`.
+pub fn clean_shutdown() {
+    if STARTED.get().is_some() && !CRASH_RECORDED.load(Ordering::SeqCst) {
+        reset_crash_counter();
+    }
+}
+
+/// Resets the counter (I.e on a clean shutdown), so the next launch starts normally
+pub fn reset_crash_counter() {
+    reset_counter_at(&counter_path());
+}
+
+fn reset_counter_at(path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    if let Err(err) = CrashCounter::default().save(path) {
+        log::error!("Unable to reset crash counter: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter_file(name: &str) -> PathBuf {
+        let path = paths::test_data_root()
+            .join("crash_counters")
+            .join(format!("{name}.json"));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn safe_mode_starts_at_the_threshold() {
+        let path = counter_file("threshold");
+        for crash in 1..=CRASH_THRESHOLD {
+            let mut counter = CrashCounter::load(&path);
+            assert!(!counter.should_enter_safe_mode());
+            counter.record_crash(format!("crash {crash}"));
+            counter.save(&path).unwrap();
+        }
+        let counter = CrashCounter::load(&path);
+        assert_eq!(counter.consecutive_crashes, CRASH_THRESHOLD);
+        assert_eq!(
+            counter.last_crash.as_deref(),
+            Some(format!("crash {CRASH_THRESHOLD}").as_str())
+        );
+        assert!(counter.should_enter_safe_mode());
+    }
+
+    #[test]
+    fn reset_clears_the_counter() {
+        let path = counter_file("reset");
+        reset_counter_at(&path);
+        // Nothing to reset, so nothing is written either
+        assert!(!path.exists());
+
+        let mut counter = CrashCounter::default();
+        for _ in 0..CRASH_THRESHOLD {
+            counter.record_crash("now".to_string());
+        }
+        counter.save(&path).unwrap();
+        reset_counter_at(&path);
+        assert_eq!(CrashCounter::load(&path), CrashCounter::default());
+    }
+
+    #[test]
+    fn unreadable_counters_count_as_no_crashes() {
+        let path = counter_file("unreadable");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+        assert_eq!(CrashCounter::load(&path), CrashCounter::default());
+        assert_eq!(
+            CrashCounter::load(&path.with_extension("missing")),
+            CrashCounter::default()
+        );
+    }
+}
@@ -0,0 +1,687 @@
+//! Byte signature (AOB) scanning, for finding code and data by what it looks like instead of a
+//! fixed offset that changes with every build.
+//!
+//! Signatures are IDA style, hex bytes with `?` or `??` as wildcards:
+//! `48 8B 05 ?? ?? ?? ?? E8 ? ? ? ?`. The scanning itself works on byte slices, [`scan_module`]
+//! reads a module's sections through [`crate::memory::accessor`] and scans those.
+use crate::memory::{MemoryAccessor, MemoryAccessorExt, MemoryError};
+use crate::paths;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{LazyLock, Mutex};
+
+/// How many match locations an ambiguous scan reports
+const MAX_REPORTED_MATCHES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    InvalidSignature {
+        signature: String,
+        message: String,
+    },
+    NotFound {
+        signature: String,
+    },
+    /// More than one match, `matches` holds the first few offsets
+    Ambiguous {
+        signature: String,
+        count: usize,
+        matches: Vec<usize>,
+    },
+    /// The module's PE headers couldn't be read
+    InvalidImage(String),
+    /// A relative offset pointing outside of the scanned bytes
+    OutOfBounds {
+        offset: usize,
+    },
+    /// Expected a `call` or `jmp` at `offset`
+    NotABranch {
+        offset: usize,
+    },
+    Memory(MemoryError),
+}
+
+impl Display for ScanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanError::InvalidSignature { signature, message } => {
+                write!(f, "Invalid signature \"{}\": {}", signature, message)
+            }
+            ScanError::NotFound { signature } => write!(f, "No match for \"{}\"", signature),
+            ScanError::Ambiguous {
+                signature,
+                count,
+                matches,
+            } => write!(
+                f,
+                "\"{}\" matched {} places (at {})",
+                signature,
+                count,
+                matches
+                    .iter()
+                    .map(|offset| format!("{:#X}", offset))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ScanError::InvalidImage(message) => write!(f, "Invalid module image: {}", message),
+            ScanError::OutOfBounds { offset } => {
+                write!(f, "Offset {:#X} is outside of the scanned bytes", offset)
+            }
+            ScanError::NotABranch { offset } => {
+                write!(f, "No call or jmp at {:#X}", offset)
+            }
+            ScanError::Memory(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ScanError {}
+
+impl From<MemoryError> for ScanError {
+    fn from(err: MemoryError) -> Self {
+        ScanError::Memory(err)
+    }
+}
+
+/// A parsed signature, `None` being a wildcard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    text: String,
+    bytes: Vec<Option<u8>>,
+}
+
+impl FromStr for Signature {
+    type Err = ScanError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let error = |message: String| ScanError::InvalidSignature {
+            signature: text.to_string(),
+            message,
+        };
+        let bytes = text
+            .split_whitespace()
+            .map(|token| match token {
+                "?" | "??" => Ok(None),
+                _ if token.len() == 2 => u8::from_str_radix(token, 16)
+                    .map(Some)
+                    .map_err(|_| error(format!("invalid byte `{}`", token))),
+                _ => Err(error(format!("invalid byte `{}`", token))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !bytes.iter().any(Option::is_some) {
+            return Err(error(
+                "needs at least one byte that isn't a wildcard".to_string(),
+            ));
+        }
+        Ok(Self {
+            text: text.to_string(),
+            bytes,
+        })
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Signature {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn matches_at(&self, data: &[u8], offset: usize) -> bool {
+        data.get(offset..offset + self.bytes.len())
+            .is_some_and(|window| {
+                window
+                    .iter()
+                    .zip(&self.bytes)
+                    .all(|(byte, expected)| expected.is_none_or(|expected| *byte == expected))
+            })
+    }
+
+    /// Every offset in `data` the signature matches at
+    pub fn find_all(&self, data: &[u8]) -> Vec<usize> {
+        if data.len() < self.bytes.len() {
+            return Vec::new();
+        }
+        // Jump between occurrences of the first fixed byte instead of checking every offset
+        let (anchor_index, anchor) = self
+            .bytes
+            .iter()
+            .enumerate()
+            .find_map(|(index, byte)| byte.map(|byte| (index, byte)))
+            .expect("signatures always have a fixed byte");
+        let last_start = data.len() - self.bytes.len();
+        data[anchor_index..=last_start + anchor_index]
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == anchor)
+            .map(|(start, _)| start)
+            .filter(|start| self.matches_at(data, *start))
+            .collect()
+    }
+
+    /// The only offset the signature matches at, an error for zero or several matches
+    pub fn find_unique(&self, data: &[u8]) -> Result<usize, ScanError> {
+        match self.find_all(data).as_slice() {
+            [] => Err(ScanError::NotFound {
+                signature: self.text.clone(),
+            }),
+            [offset] => Ok(*offset),
+            matches => Err(ScanError::Ambiguous {
+                signature: self.text.clone(),
+                count: matches.len(),
+                matches: matches.iter().copied().take(MAX_REPORTED_MATCHES).collect(),
+            }),
+        }
+    }
+}
+
+/// Resolves a RIP-relative operand, returning the offset into `data` it points at
+///
+/// `instruction` is the offset of the instruction, `displacement` how far into it the 32 bit
+/// displacement is and `length` the full instruction length (I.e 3 and 7 for
+/// `48 8B 05 xx xx xx xx`, `mov rax, [rip+xx]`).
+pub fn rip_relative(
+    data: &[u8],
+    instruction: usize,
+    displacement: usize,
+    length: usize,
+) -> Result<usize, ScanError> {
+    let at = instruction + displacement;
+    let bytes: [u8; 4] = data
+        .get(at..at + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ScanError::OutOfBounds { offset: at })?;
+    let target = (instruction + length) as isize + i32::from_le_bytes(bytes) as isize;
+    usize::try_from(target).map_err(|_| ScanError::OutOfBounds { offset: at })
+}
+
+/// The target of the `call rel32` (`E8`) or `jmp rel32` (`E9`) at `instruction`
+pub fn call_target(data: &[u8], instruction: usize) -> Result<usize, ScanError> {
+    match data.get(instruction) {
+        Some(0xE8 | 0xE9) => rip_relative(data, instruction, 1, 5),
+        _ => Err(ScanError::NotABranch {
+            offset: instruction,
+        }),
+    }
+}
+
+/// [`rip_relative`] for an instruction in memory, returning the absolute address it points at
+pub fn resolve_rip_relative(
+    memory: &dyn MemoryAccessor,
+    address: usize,
+    displacement: usize,
+    length: usize,
+) -> Result<usize, ScanError> {
+    // Displacements are rarely aligned, so this reads bytes instead of an i32
+    let mut bytes = [0; 4];
+    memory.read_bytes_checked(address + displacement, &mut bytes)?;
+    (address + length)
+        .checked_add_signed(i32::from_le_bytes(bytes) as isize)
+        .ok_or(ScanError::OutOfBounds {
+            offset: address + displacement,
+        })
+}
+
+/// [`call_target`] for an instruction in memory, returning the absolute address called
+pub fn resolve_call_target(
+    memory: &dyn MemoryAccessor,
+    address: usize,
+) -> Result<usize, ScanError> {
+    match memory.read_checked::<u8>(address)? {
+        0xE8 | 0xE9 => resolve_rip_relative(memory, address, 1, 5),
+        _ => Err(ScanError::NotABranch { offset: address }),
+    }
+}
+
+/// A section from a module's PE headers, offsets relative to the module base
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub offset: usize,
+    pub size: usize,
+    pub executable: bool,
+}
+
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Reads the section table out of the start of a module image
+pub fn parse_sections(headers: &[u8]) -> Result<Vec<Section>, ScanError> {
+    let invalid = |message: &str| ScanError::InvalidImage(message.to_string());
+    if headers.get(..2) != Some(b"MZ") {
+        return Err(invalid("missing MZ header"));
+    }
+    let nt = read_u32(headers, 0x3C).ok_or_else(|| invalid("truncated DOS header"))? as usize;
+    if headers.get(nt..nt + 4) != Some(b"PE\0\0") {
+        return Err(invalid("missing PE signature"));
+    }
+    let count = read_u16(headers, nt + 6).ok_or_else(|| invalid("truncated file header"))?;
+    let optional_size =
+        read_u16(headers, nt + 20).ok_or_else(|| invalid("truncated file header"))? as usize;
+    let table = nt + 24 + optional_size;
+    (0..count as usize)
+        .map(|index| {
+            let entry = table + index * 40;
+            let raw_name = headers
+                .get(entry..entry + 8)
+                .ok_or_else(|| invalid("truncated section table"))?;
+            let name = String::from_utf8_lossy(raw_name)
+                .trim_end_matches('\0')
+                .to_string();
+            let size = read_u32(headers, entry + 8).ok_or_else(|| invalid("truncated section"))?;
+            let offset =
+                read_u32(headers, entry + 12).ok_or_else(|| invalid("truncated section"))?;
+            let flags =
+                read_u32(headers, entry + 36).ok_or_else(|| invalid("truncated section"))?;
+            Ok(Section {
+                name,
+                offset: offset as usize,
+                size: size as usize,
+                executable: flags & IMAGE_SCN_MEM_EXECUTE != 0,
+            })
+        })
+        .collect()
+}
+
+/// Which sections of a module to scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sections<'a> {
+    All,
+    Executable,
+    Named(&'a str),
+}
+
+impl Sections<'_> {
+    fn includes(&self, section: &Section) -> bool {
+        match self {
+            Sections::All => true,
+            Sections::Executable => section.executable,
+            Sections::Named(name) => section.name == *name,
+        }
+    }
+}
+
+/// Scans the sections of the module at `base`, returning the module offset of the single match
+pub fn scan_image(
+    memory: &dyn MemoryAccessor,
+    base: usize,
+    signature: &Signature,
+    sections: Sections,
+) -> Result<usize, ScanError> {
+    let mut headers = vec![0; 0x1000];
+    memory.read_bytes_checked(base, &mut headers)?;
+    let mut matches = Vec::new();
+    for section in parse_sections(&headers)?
+        .iter()
+        .filter(|section| sections.includes(section))
+    {
+        let mut data = vec![0; section.size];
+        memory.read_bytes_checked(base + section.offset, &mut data)?;
+        matches.extend(
+            signature
+                .find_all(&data)
+                .into_iter()
+                .map(|offset| section.offset + offset),
+        );
+    }
+    match matches.as_slice() {
+        [] => Err(ScanError::NotFound {
+            signature: signature.to_string(),
+        }),
+        [offset] => Ok(*offset),
+        _ => Err(ScanError::Ambiguous {
+            signature: signature.to_string(),
+            count: matches.len(),
+            matches: matches.into_iter().take(MAX_REPORTED_MATCHES).collect(),
+        }),
+    }
+}
+
+/// Scan results for one build, module offsets keyed by module, name and signature (I.e
+/// `dmc3.exe:give_item:48 8B 05 ?? ?? ?? ??`)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanCache {
+    pub build_hash: u64,
+    pub offsets: BTreeMap<String, usize>,
+    /// Keys that were scanned and found unique during this run
+    #[serde(skip)]
+    verified: BTreeSet<String>,
+}
+
+impl ScanCache {
+    pub fn new(build_hash: u64) -> Self {
+        Self {
+            build_hash,
+            offsets: BTreeMap::new(),
+            verified: BTreeSet::new(),
+        }
+    }
+
+    /// The key `name` is cached under, a different signature never reuses an old result
+    pub fn key(module_name: &str, name: &str, signature: &Signature) -> String {
+        format!(
+            "{}:{}:{}",
+            module_name.to_lowercase(),
+            name,
+            signature
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+
+    /// Takes the result of a full scan for `key`, returning whether the file needs saving
+    fn update(&mut self, key: String, result: &Result<usize, ScanError>) -> bool {
+        match result {
+            Ok(offset) => {
+                let previous = self.offsets.insert(key.clone(), *offset);
+                if let Some(previous) = previous.filter(|previous| previous != offset) {
+                    log::warn!(
+                        "{} moved from {:#X} to {:#X} on the same build",
+                        key,
+                        previous,
+                        offset
+                    );
+                }
+                self.verified.insert(key);
+                previous != Some(*offset)
+            }
+            Err(_) => {
+                self.verified.remove(&key);
+                self.offsets.remove(&key).is_some()
+            }
+        }
+    }
+
+    /// Reads the cache for `build_hash` from `dir`, starting empty if there is none
+    pub fn load(dir: &Path, build_hash: u64) -> Self {
+        fs::read_to_string(Self::path_in(dir, build_hash))
+            .ok()
+            .and_then(|text| serde_json::from_str::<ScanCache>(&text).ok())
+            .filter(|cache| cache.build_hash == build_hash)
+            .unwrap_or_else(|| Self::new(build_hash))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(dir)?;
+        fs::write(
+            Self::path_in(dir, self.build_hash),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    fn path_in(dir: &Path, build_hash: u64) -> PathBuf {
+        dir.join(format!("{:016x}.json", build_hash))
+    }
+}
+
+/// `<data root>/archipelago/scan_cache`
+pub fn cache_dir() -> PathBuf {
+    paths::archipelago_dir().join("scan_cache")
+}
+
+static CACHE: LazyLock<Mutex<Option<ScanCache>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Finds `signature` in `module_name` and returns its absolute address
+///
+/// Results are cached per `build_hash` (I.e [`crate::dmc::versions::VersionInformation::hash`]),
+/// both in memory and in [`cache_dir`]. Every signature is still scanned in full once per run, so
+/// one that became ambiguous is reported even if it has a cached offset, later lookups in the
+/// same run reuse that scan.
+pub fn scan_module(
+    name: &str,
+    module_name: &str,
+    signature: &str,
+    sections: Sections,
+    build_hash: u64,
+) -> Result<usize, Box<dyn Error>> {
+    let signature: Signature = signature.parse()?;
+    let base = crate::get_base_address(module_name);
+    if base == 0 {
+        return Err(format!("{} is not loaded", module_name).into());
+    }
+    let memory = crate::memory::accessor();
+    let key = ScanCache::key(module_name, name, &signature);
+    let mut cache = match CACHE.lock() {
+        Ok(cache) => cache,
+        Err(err) => {
+            return Err(format!("PoisonError upon trying to use the scan cache {:?}", err).into());
+        }
+    };
+    let cache = match cache.as_mut() {
+        Some(cache) if cache.build_hash == build_hash => cache,
+        _ => cache.insert(ScanCache::load(&cache_dir(), build_hash)),
+    };
+    if cache.verified.contains(&key)
+        && let Some(&offset) = cache.offsets.get(&key)
+    {
+        let mut data = vec![0; signature.len()];
+        if memory
+            .read_bytes_checked(base + offset, &mut data)
+            .is_ok_and(|_| signature.matches_at(&data, 0))
+        {
+            return Ok(base + offset);
+        }
+        log::warn!("{} no longer matches at {:#X}, scanning again", key, offset);
+    }
+    let result = scan_image(memory.as_ref(), base, &signature, sections);
+    if cache.update(key, &result)
+        && let Err(err) = cache.save(&cache_dir())
+    {
+        log::error!("Unable to save scan cache: {}", err);
+    }
+    let offset = result?;
+    log::debug!("Found {} at {}+{:#X}", name, module_name, offset);
+    Ok(base + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{MockMemory, PAGE_SIZE, Protection};
+
+    fn signature(text: &str) -> Signature {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        assert!("48 8B 0".parse::<Signature>().is_err());
+        assert!("48 ZZ".parse::<Signature>().is_err());
+        assert!("?? ??".parse::<Signature>().is_err());
+    }
+
+    #[test]
+    fn find_all_handles_wildcards_and_edges() {
+        let data = [0x48, 0x8B, 0x05, 0x00, 0x48, 0x8B, 0x0D, 0x48, 0x8B];
+        assert_eq!(signature("48 8B ??").find_all(&data), vec![0, 4]);
+        assert_eq!(signature("?? 8B 05").find_all(&data), vec![0]);
+        // A match can't run past the end of the data
+        assert_eq!(signature("48 8B").find_all(&data), vec![0, 4, 7]);
+        assert!(
+            signature("8B ?? ?? ?? ?? ?? ?? ?? ??")
+                .find_all(&data)
+                .is_empty()
+        );
+        assert!(signature("48").find_all(&[]).is_empty());
+    }
+
+    #[test]
+    fn find_unique_reports_missing_and_ambiguous_signatures() {
+        let data = [0xCC, 0xE8, 0x01, 0xCC, 0xE8, 0x02];
+        assert_eq!(signature("E8 02").find_unique(&data), Ok(4));
+        assert_eq!(
+            signature("E8 03").find_unique(&data),
+            Err(ScanError::NotFound {
+                signature: "E8 03".to_string()
+            })
+        );
+        assert_eq!(
+            signature("E8 ??").find_unique(&data),
+            Err(ScanError::Ambiguous {
+                signature: "E8 ??".to_string(),
+                count: 2,
+                matches: vec![1, 4],
+            })
+        );
+    }
+
+    #[test]
+    fn resolves_rip_relative_operands() {
+        // mov rax, [rip+0x10] at 2, then mov rax, [rip-0x9] at 9
+        let data = [
+            0x90, 0x90, 0x48, 0x8B, 0x05, 0x10, 0x00, 0x00, 0x00, 0x48, 0x8B, 0x05, 0xF7, 0xFF,
+            0xFF, 0xFF,
+        ];
+        assert_eq!(rip_relative(&data, 2, 3, 7), Ok(0x19));
+        assert_eq!(rip_relative(&data, 9, 3, 7), Ok(7));
+        assert_eq!(
+            rip_relative(&data, 12, 3, 7),
+            Err(ScanError::OutOfBounds { offset: 15 })
+        );
+        // Pointing before the start of the data
+        let data = [0x48, 0x8B, 0x05, 0xF0, 0xFF, 0xFF, 0xFF];
+        assert_eq!(
+            rip_relative(&data, 0, 3, 7),
+            Err(ScanError::OutOfBounds { offset: 3 })
+        );
+    }
+
+    #[test]
+    fn resolves_call_and_jump_targets() {
+        let data = [
+            0xE8, 0x05, 0x00, 0x00, 0x00, 0xE9, 0xFB, 0xFF, 0xFF, 0xFF, 0x90,
+        ];
+        assert_eq!(call_target(&data, 0), Ok(10));
+        assert_eq!(call_target(&data, 5), Ok(5));
+        assert_eq!(
+            call_target(&data, 10),
+            Err(ScanError::NotABranch { offset: 10 })
+        );
+        assert_eq!(
+            call_target(&data, 11),
+            Err(ScanError::NotABranch { offset: 11 })
+        );
+    }
+
+    const MODULE_BASE: usize = 0x1_4000_0000;
+
+    /// A header page with `.text` (executable) and `.data` sections, one page each
+    fn image(text: &[u8], data: &[u8]) -> MockMemory {
+        let mut headers = vec![0; PAGE_SIZE];
+        headers[..2].copy_from_slice(b"MZ");
+        headers[0x3C..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        headers[0x80..0x84].copy_from_slice(b"PE\0\0");
+        headers[0x86..0x88].copy_from_slice(&2u16.to_le_bytes());
+        headers[0x94..0x96].copy_from_slice(&0xF0u16.to_le_bytes());
+        let table = 0x80 + 24 + 0xF0;
+        for (index, (name, flags)) in [(b".text", 0x6000_0020u32), (b".data", 0xC000_0040)]
+            .iter()
+            .enumerate()
+        {
+            let entry = table + index * 40;
+            headers[entry..entry + 5].copy_from_slice(*name);
+            headers[entry + 8..entry + 12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+            headers[entry + 12..entry + 16]
+                .copy_from_slice(&(((index + 1) * PAGE_SIZE) as u32).to_le_bytes());
+            headers[entry + 36..entry + 40].copy_from_slice(&flags.to_le_bytes());
+        }
+        let memory = MockMemory::new();
+        memory.add_module("game.exe", MODULE_BASE, 3 * PAGE_SIZE);
+        memory.load(MODULE_BASE, &headers, Protection::ReadOnly);
+        memory.map(MODULE_BASE + PAGE_SIZE, PAGE_SIZE, Protection::ExecuteRead);
+        memory.load(MODULE_BASE + PAGE_SIZE, text, Protection::ExecuteRead);
+        memory.map(
+            MODULE_BASE + 2 * PAGE_SIZE,
+            PAGE_SIZE,
+            Protection::ReadWrite,
+        );
+        memory.load(MODULE_BASE + 2 * PAGE_SIZE, data, Protection::ReadWrite);
+        memory
+    }
+
+    #[test]
+    fn parses_the_section_table() {
+        let memory = image(&[], &[]);
+        let headers = memory.peek(MODULE_BASE, PAGE_SIZE).unwrap();
+        let sections = parse_sections(&headers).unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].name, ".text");
+        assert_eq!(sections[0].offset, PAGE_SIZE);
+        assert!(sections[0].executable);
+        assert_eq!(sections[1].name, ".data");
+        assert!(!sections[1].executable);
+        assert!(parse_sections(&headers[2..]).is_err());
+    }
+
+    #[test]
+    fn scan_image_respects_sections_and_ambiguity() {
+        let memory = image(&[0x90, 0xDE, 0xAD, 0xBE, 0xEF], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        let dead_beef = signature("DE AD BE EF");
+        assert_eq!(
+            scan_image(&memory, MODULE_BASE, &dead_beef, Sections::Executable),
+            Ok(PAGE_SIZE + 1)
+        );
+        assert_eq!(
+            scan_image(&memory, MODULE_BASE, &dead_beef, Sections::Named(".data")),
+            Ok(2 * PAGE_SIZE)
+        );
+        assert_eq!(
+            scan_image(&memory, MODULE_BASE, &dead_beef, Sections::All),
+            Err(ScanError::Ambiguous {
+                signature: "DE AD BE EF".to_string(),
+                count: 2,
+                matches: vec![PAGE_SIZE + 1, 2 * PAGE_SIZE],
+            })
+        );
+    }
+
+    #[test]
+    fn cache_keys_include_the_signature() {
+        let first = ScanCache::key("Game.exe", "give_item", &signature("48 8B  05"));
+        assert_eq!(first, "game.exe:give_item:48 8B 05");
+        assert_ne!(
+            first,
+            ScanCache::key("game.exe", "give_item", &signature("48 8B 0D"))
+        );
+    }
+
+    #[test]
+    fn failed_scans_drop_cached_offsets() {
+        let mut cache = ScanCache::new(1);
+        let key = "game.exe:give_item:48 8B 05".to_string();
+        assert!(cache.update(key.clone(), &Ok(0x10)));
+        assert!(!cache.update(key.clone(), &Ok(0x10)));
+        assert!(cache.verified.contains(&key));
+        let ambiguous = Err(ScanError::Ambiguous {
+            signature: "48 8B 05".to_string(),
+            count: 2,
+            matches: vec![0x10, 0x20],
+        });
+        assert!(cache.update(key.clone(), &ambiguous));
+        assert!(!cache.offsets.contains_key(&key));
+        assert!(!cache.verified.contains(&key));
+    }
+}
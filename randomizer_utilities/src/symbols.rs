@@ -0,0 +1,203 @@
+//! Symbol maps for known builds, so crash addresses read `dmc3.exe!GiveItem+0x1A` instead of a bare
+//! offset.
+//!
+//! Maps are plain text with one symbol per line: `name start_offset length`, offsets and lengths
+//! in hex (the `0x` is optional). The name is everything before the last two fields, so it can
+//! contain spaces. Empty lines and lines starting with `#` are ignored.
+//! ```text
+//! # dmc3.exe, Steam build
+//! GiveItem 0x1A2B30 0x120
+//! CPlayer::update 0x1B0000 0x4F0
+//! ```
+use crate::paths;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// Offset from the module base
+    pub start: usize,
+    pub length: usize,
+}
+
+#[derive(Debug)]
+pub struct SymbolMapError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for SymbolMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for SymbolMapError {}
+
+fn parse_hex(value: &str) -> Option<usize> {
+    let value = value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+        .unwrap_or(value);
+    usize::from_str_radix(value, 16).ok()
+}
+
+/// The symbols of one module, sorted by start offset
+#[derive(Debug, Clone, Default)]
+pub struct SymbolMap {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<Self, SymbolMapError> {
+        let mut symbols = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| SymbolMapError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [name @ .., start, length] = fields.as_slice() else {
+                return Err(error("expected `name start_offset length`"));
+            };
+            if name.is_empty() {
+                return Err(error("expected `name start_offset length`"));
+            }
+            symbols.push(Symbol {
+                name: name.join(" "),
+                start: parse_hex(start).ok_or_else(|| error("invalid start offset"))?,
+                length: parse_hex(length).ok_or_else(|| error("invalid length"))?,
+            });
+        }
+        symbols.sort_by_key(|symbol| symbol.start);
+        Ok(Self { symbols })
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// The symbol covering `offset`, along with how far into it `offset` is
+    pub fn lookup(&self, offset: usize) -> Option<(&Symbol, usize)> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.start <= offset)
+            .checked_sub(1)?;
+        let symbol = &self.symbols[index];
+        let into = offset - symbol.start;
+        (into < symbol.length).then_some((symbol, into))
+    }
+}
+
+// Keyed by lowercase module name
+static SYMBOL_MAPS: LazyLock<RwLock<HashMap<String, SymbolMap>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// `<data root>/archipelago/symbols`
+pub fn symbols_dir() -> PathBuf {
+    paths::archipelago_dir().join("symbols")
+}
+
+pub fn register_symbol_map(module_name: &str, map: SymbolMap) {
+    match SYMBOL_MAPS.write() {
+        Ok(mut maps) => {
+            maps.insert(module_name.to_lowercase(), map);
+        }
+        Err(err) => {
+            log::error!("PoisonError upon trying to register symbol map {:?}", err);
+        }
+    }
+}
+
+/// Reads the map at `path` and uses it for `module_name`, returning how many symbols it had
+pub fn load_symbol_map(module_name: &str, path: &Path) -> Result<usize, Box<dyn Error>> {
+    let map = SymbolMap::parse(&fs::read_to_string(path)?)?;
+    let count = map.len();
+    register_symbol_map(module_name, map);
+    log::debug!(
+        "Loaded {} symbols for {} from {}",
+        count,
+        module_name,
+        path.display()
+    );
+    Ok(count)
+}
+
+/// `GiveItem+0x1A` for an offset into `module_name`, if a map covers it
+///
+/// Doesn't wait on the maps, so it's safe to call from the exception handler.
+pub fn symbolize(module_name: &str, offset: usize) -> Option<String> {
+    let maps = SYMBOL_MAPS.try_read().ok()?;
+    let (symbol, into) = maps.get(&module_name.to_lowercase())?.lookup(offset)?;
+    Some(format!("{}+0x{:X}", symbol.name, into))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "
+# dmc3.exe, Steam build
+
+CPlayer::update 0x1B0000 0x4F0
+GiveItem 1A2B30 120
+    # indented comment
+operator new 0x200000 0x10
+";
+
+    #[test]
+    fn parses_names_with_spaces_and_skips_comments() {
+        let map = SymbolMap::parse(MAP).unwrap();
+        assert_eq!(map.len(), 3);
+        // Sorted by start offset, not file order
+        assert_eq!(map.symbols[0].name, "GiveItem");
+        assert_eq!(map.symbols[0].start, 0x1A2B30);
+        assert_eq!(map.symbols[0].length, 0x120);
+        assert_eq!(map.symbols[2].name, "operator new");
+        assert!(SymbolMap::parse("# only a comment\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        for (text, line, message) in [
+            ("GiveItem 0x10", 1, "expected `name start_offset length`"),
+            (
+                "# header\n0x10 0x20",
+                2,
+                "expected `name start_offset length`",
+            ),
+            ("GiveItem 0xZZ 0x20", 1, "invalid start offset"),
+            ("\nGiveItem 0x10 twenty", 2, "invalid length"),
+        ] {
+            let err = SymbolMap::parse(text).unwrap_err();
+            assert_eq!((err.line, err.message.as_str()), (line, message), "{text}");
+        }
+    }
+
+    #[test]
+    fn lookup_finds_the_covering_symbol() {
+        let map = SymbolMap::parse(MAP).unwrap();
+        let (symbol, into) = map.lookup(0x1A2B30 + 0x1A).unwrap();
+        assert_eq!((symbol.name.as_str(), into), ("GiveItem", 0x1A));
+        let (symbol, into) = map.lookup(0x1B0000).unwrap();
+        assert_eq!((symbol.name.as_str(), into), ("CPlayer::update", 0));
+        // Past the end of a symbol, before the next one starts
+        assert!(map.lookup(0x1A2B30 + 0x120).is_none());
+        // Before the first symbol
+        assert!(map.lookup(0x1000).is_none());
+        assert!(map.lookup(0).is_none());
+    }
+}
@@ -1,14 +1,17 @@
+use crate::panic_handler::guard_hook;
 use crate::ui::dx11_state::STATE;
 use crate::ui::dx11_types::{
     D3D11CreateDeviceAndSwapChain, ORIGINAL_DEV_CHAIN, ORIGINAL_PRESENT, ORIGINAL_RESIZE_BUFFERS,
-    ResizeBuffersFn,
+    PresentFn, ResizeBuffersFn,
 };
 use std::error::Error;
 use std::ffi::c_void;
 use std::fmt::Debug;
+use std::mem::ManuallyDrop;
 use std::ptr;
 use std::sync::OnceLock;
-use windows::Win32::Foundation::HMODULE;
+use std::sync::atomic::{AtomicBool, Ordering};
+use windows::Win32::Foundation::{E_FAIL, HMODULE};
 use windows::Win32::Graphics::Direct3D::{D3D_DRIVER_TYPE, D3D_FEATURE_LEVEL};
 use windows::Win32::Graphics::Direct3D11::D3D11_CREATE_DEVICE_FLAG;
 use windows::Win32::Graphics::Dxgi::{
@@ -19,6 +22,10 @@ use windows::core::HRESULT;
 pub static OVERLAY_HANDLER: OnceLock<crate::dmc::dmc_helpers::OverlayHandler> = OnceLock::new();
 
 pub fn setup_overlay() {
+    if crate::safe_mode::is_safe_mode() {
+        log::warn!("Safe mode, not setting up the overlay");
+        return;
+    }
     log::info!("Setting up Archipelago Randomizer Overlay");
     install(
         OVERLAY_HANDLER.get().unwrap().create_device_addr as *mut D3D11CreateDeviceAndSwapChain,
@@ -85,8 +92,14 @@ unsafe extern "system" fn hook_d3d11_create_device_and_swap_chain(
     pfeaturelevel: *mut D3D_FEATURE_LEVEL,
     ppimmediatecontext: *mut *mut c_void,
 ) -> HRESULT {
+    let Some(original) = ORIGINAL_DEV_CHAIN.get() else {
+        log::error!("Device and swap chain hook called before the original was saved");
+        return E_FAIL;
+    };
+    // Called outside the guard, so a panic while hooking below can't turn a successful call into
+    // E_FAIL and leak the device and swap chain it created
     let res = unsafe {
-        ORIGINAL_DEV_CHAIN.get().unwrap()(
+        original(
             padapter,
             drivertype,
             software,
@@ -101,36 +114,72 @@ unsafe extern "system" fn hook_d3d11_create_device_and_swap_chain(
             ppimmediatecontext,
         )
     };
-    match install_vtable_hook(
-        ppswapchain,
-        8,
-        OVERLAY_HANDLER.get().unwrap().present_fn,
-        &ORIGINAL_PRESENT,
-    ) {
-        Ok(_) => {
-            log::debug!("Installed present hook");
-        }
-        Err(err) => {
-            log::error!("Failed to install present hook: {}", err);
-        }
+    if res.is_err() {
+        return res;
     }
-
-    match install_vtable_hook(
-        ppswapchain,
-        13,
-        resize_hook as ResizeBuffersFn,
-        &ORIGINAL_RESIZE_BUFFERS,
-    ) {
-        Ok(_) => {
-            log::debug!("Installed resize hook");
+    guard_hook("create device and swap chain", (), || {
+        match install_vtable_hook(ppswapchain, 8, present_hook as PresentFn, &ORIGINAL_PRESENT) {
+            Ok(_) => {
+                log::debug!("Installed present hook");
+            }
+            Err(err) => {
+                log::error!("Failed to install present hook: {}", err);
+            }
         }
-        Err(err) => {
-            log::error!("Failed to install resize hook: {}", err);
+
+        match install_vtable_hook(
+            ppswapchain,
+            13,
+            resize_hook as ResizeBuffersFn,
+            &ORIGINAL_RESIZE_BUFFERS,
+        ) {
+            Ok(_) => {
+                log::debug!("Installed resize hook");
+            }
+            Err(err) => {
+                log::error!("Failed to install resize hook: {}", err);
+            }
         }
-    }
+    });
     res
 }
 
+/// Set once the overlay panicked while presenting, the game presents on its own from then on
+static OVERLAY_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Runs the overlay's present (I.e [`crate::dmc::dmc_helpers::OverlayHandler::present_fn`]) under
+/// a guard, falling back to the original present if it panics
+unsafe extern "system" fn present_hook(
+    swap_chain: IDXGISwapChain,
+    sync_interval: u32,
+    flags: u32,
+) -> i32 {
+    // The game only lent the swap chain, it's never released here, even if the overlay unwinds
+    let swap_chain = ManuallyDrop::new(swap_chain);
+    let present = OVERLAY_HANDLER
+        .get()
+        .filter(|_| !OVERLAY_FAILED.load(Ordering::SeqCst))
+        .map(|handler| handler.present_fn);
+    if let Some(present) = present
+        && let Some(result) = guard_hook("present", None, || {
+            // Lent on the same terms, without giving up the one kept for the fallback
+            let lent: IDXGISwapChain = unsafe { std::mem::transmute_copy(&*swap_chain) };
+            Some(unsafe { present(lent, sync_interval, flags) })
+        })
+    {
+        return result;
+    }
+    if present.is_some() && !OVERLAY_FAILED.swap(true, Ordering::SeqCst) {
+        log::error!("Overlay disabled after a panic while presenting");
+    }
+    match ORIGINAL_PRESENT.get() {
+        Some(original) => unsafe {
+            original(ManuallyDrop::into_inner(swap_chain), sync_interval, flags)
+        },
+        None => E_FAIL.0,
+    }
+}
+
 pub(crate) unsafe extern "system" fn resize_hook(
     swap_chain: *mut IDXGISwapChain,
     buffer_count: u32,
@@ -139,25 +188,27 @@ pub(crate) unsafe extern "system" fn resize_hook(
     new_format: Common::DXGI_FORMAT,
     swap_chain_flags: DXGI_SWAP_CHAIN_FLAG,
 ) {
-    unsafe {
-        ORIGINAL_RESIZE_BUFFERS.get().unwrap()(
-            swap_chain,
-            buffer_count,
-            width,
-            height,
-            new_format,
-            swap_chain_flags,
-        )
-    };
-    if let Some(state) = STATE.get() {
-        match state.write() {
-            Ok(mut state) => {
-                state.rtv = None;
-                state.atlas = None;
-            }
-            Err(err) => {
-                log::error!("Unable to edit D3D11State {}", err)
+    guard_hook("resize buffers", (), || {
+        unsafe {
+            ORIGINAL_RESIZE_BUFFERS.get().unwrap()(
+                swap_chain,
+                buffer_count,
+                width,
+                height,
+                new_format,
+                swap_chain_flags,
+            )
+        };
+        if let Some(state) = STATE.get() {
+            match state.write() {
+                Ok(mut state) => {
+                    state.rtv = None;
+                    state.atlas = None;
+                }
+                Err(err) => {
+                    log::error!("Unable to edit D3D11State {}", err)
+                }
             }
         }
-    }
+    })
 }
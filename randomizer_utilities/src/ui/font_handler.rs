@@ -1,17 +1,30 @@
+#[cfg(windows)]
 use crate::ui::dx11_state;
+#[cfg(windows)]
 use crate::ui::dx11_state::SHADERS;
 use fontdue::Font;
 use std::collections::HashMap;
+#[cfg(windows)]
 use std::slice::from_raw_parts;
-use std::sync::{LazyLock, OnceLock};
+use std::sync::LazyLock;
+#[cfg(windows)]
+use std::sync::OnceLock;
+#[cfg(windows)]
 use windows::Win32::Foundation::{FALSE, TRUE};
+#[cfg(windows)]
 use windows::Win32::Graphics::Direct3D::D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST;
+#[cfg(windows)]
 use windows::Win32::Graphics::Direct3D11::*;
+#[cfg(windows)]
 use windows::Win32::Graphics::Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC};
 
+#[cfg(windows)]
 static BLEND_STATE: OnceLock<ID3D11BlendState> = OnceLock::new();
+#[cfg(windows)]
 static SAMPLER: OnceLock<ID3D11SamplerState> = OnceLock::new();
+#[cfg(windows)]
 static FONT_COLOR: OnceLock<ID3D11Buffer> = OnceLock::new();
+#[cfg(windows)]
 static D3D_SHADERS: OnceLock<(ID3D11VertexShader, ID3D11PixelShader)> = OnceLock::new();
 
 static FONT: LazyLock<Font> = LazyLock::new(|| {
@@ -31,6 +44,7 @@ pub struct GlyphInfo {
 }
 
 pub struct FontAtlas {
+    #[cfg(windows)]
     pub texture: Option<ID3D11ShaderResourceView>,
     pub glyphs: HashMap<char, GlyphInfo>,
     pub atlas_width: u32,
@@ -83,12 +97,16 @@ pub const RED: FontColorCB = FontColorCB::new(1.0, 0.0, 0.0, 1.0);
 pub const GREEN: FontColorCB = FontColorCB::new(0.0, 1.0, 0.0, 1.0);
 pub const YELLOW: FontColorCB = FontColorCB::new(0.98, 0.98, 0.824, 1.0); // Used for other slots
 
-pub fn create_rgba_font_atlas(
-    device: &ID3D11Device,
-    chars: &[char],
-    font_size: f32,
-    max_row_width: u32,
-) -> Option<FontAtlas> {
+/// CPU side copy of the atlas, before it gets uploaded as a texture
+pub struct PackedAtlas {
+    pub data: Vec<u8>, // RGBA
+    pub glyphs: HashMap<char, GlyphInfo>,
+    pub atlas_width: u32,
+    pub atlas_height: u32,
+}
+
+/// Rasterizes the given characters and packs them row by row into an RGBA bitmap
+pub fn pack_rgba_font_atlas(chars: &[char], font_size: f32, max_row_width: u32) -> PackedAtlas {
     let mut glyph_bitmaps: Vec<(char, Vec<u8>, fontdue::Metrics)> = Vec::new();
     for &c in chars {
         let (metrics, bitmap) = (*FONT).rasterize(c, font_size);
@@ -184,6 +202,28 @@ pub fn create_rgba_font_atlas(
         y_offset += row_height;
     }
 
+    PackedAtlas {
+        data: atlas_data,
+        glyphs: glyph_infos,
+        atlas_width,
+        atlas_height,
+    }
+}
+
+#[cfg(windows)]
+pub fn create_rgba_font_atlas(
+    device: &ID3D11Device,
+    chars: &[char],
+    font_size: f32,
+    max_row_width: u32,
+) -> Option<FontAtlas> {
+    let PackedAtlas {
+        data: atlas_data,
+        glyphs: glyph_infos,
+        atlas_width,
+        atlas_height,
+    } = pack_rgba_font_atlas(chars, font_size, max_row_width);
+
     let desc = D3D11_TEXTURE2D_DESC {
         Width: atlas_width,
         Height: atlas_height,
@@ -298,6 +338,7 @@ pub fn glyph_vertices(quad: &GlyphQuad, screen_width: f32, screen_height: f32) -
     ]
 }
 
+#[cfg(windows)]
 pub fn draw_string(
     state: &dx11_state::D3D11State,
     text: &str,
@@ -456,6 +497,7 @@ pub fn draw_string(
     }
 }
 
+#[cfg(windows)]
 pub fn set_shaders(device: &&ID3D11Device) -> (ID3D11VertexShader, ID3D11PixelShader) {
     let (psb, vsb) = &*SHADERS;
     let mut vs: Option<ID3D11VertexShader> = None;
@@ -478,3 +520,47 @@ pub fn set_shaders(device: &&ID3D11Device) -> (ID3D11VertexShader, ID3D11PixelSh
     }
     (vs.unwrap(), ps.unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_every_glyph_inside_the_atlas() {
+        let chars: Vec<char> = "AaBbgjQ!? ".chars().collect();
+        let atlas = pack_rgba_font_atlas(&chars, 24.0, 64);
+        assert_eq!(atlas.atlas_width, 64);
+        assert!(atlas.atlas_height > 0);
+        assert_eq!(
+            atlas.data.len(),
+            (atlas.atlas_width * atlas.atlas_height * 4) as usize
+        );
+        assert_eq!(atlas.glyphs.len(), chars.len());
+        for (c, glyph) in &atlas.glyphs {
+            assert!(glyph.x + glyph.width <= atlas.atlas_width, "{c}");
+            assert!(glyph.y + glyph.height <= atlas.atlas_height, "{c}");
+        }
+        // Whitespace has nothing to draw but still moves the cursor
+        let space = &atlas.glyphs[&' '];
+        assert_eq!((space.width, space.height), (0, 0));
+        assert!(space.advance > 0);
+    }
+
+    #[test]
+    fn glyphs_do_not_overlap() {
+        let chars: Vec<char> = ('A'..='Z').collect();
+        let atlas = pack_rgba_font_atlas(&chars, 32.0, 128);
+        let glyphs: Vec<&GlyphInfo> = atlas.glyphs.values().collect();
+        for (index, a) in glyphs.iter().enumerate() {
+            for b in &glyphs[index + 1..] {
+                let apart = a.x + a.width <= b.x
+                    || b.x + b.width <= a.x
+                    || a.y + a.height <= b.y
+                    || b.y + b.height <= a.y;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+        // Some rows had to wrap at this width
+        assert!(glyphs.iter().any(|glyph| glyph.y > 0));
+    }
+}
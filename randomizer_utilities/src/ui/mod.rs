@@ -1,6 +1,10 @@
+#[cfg(all(windows, feature = "dmc"))]
 pub mod dx11_hooks;
+#[cfg(windows)]
 pub mod dx11_state;
+#[cfg(windows)]
 pub mod dx11_state_guard;
+#[cfg(windows)]
 pub mod dx11_types;
 pub mod font_handler;
 pub mod overlay_messages;
@@ -1,4 +1,6 @@
+#[cfg(windows)]
 use crate::ui::dx11_state::D3D11State;
+#[cfg(windows)]
 use crate::ui::font_handler;
 use crate::ui::font_handler::FontColorCB;
 use archipelago_rs::LocatedItem;
@@ -46,6 +48,10 @@ impl OverlayMessage {
             _msg_type: msg_type,
         }
     }
+
+    pub fn segments(&self) -> &[MessageSegment] {
+        &self.segments
+    }
 }
 
 // TODO This doesn't matter right now, but it could be used later
@@ -65,6 +71,22 @@ pub fn add_message(overlay: OverlayMessage) {
     }
 }
 
+/// Logs the text of every message that hasn't been shown yet
+pub fn dump_queue() {
+    let Ok(queue) = MESSAGE_QUEUE.try_lock() else {
+        return;
+    };
+    for message in queue.iter() {
+        let text: String = message
+            .segments()
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect();
+        log::info!("Pending overlay message: {}", text);
+    }
+}
+
+#[cfg(windows)]
 pub fn draw_colored_message(
     state: &D3D11State,
     msg: &TimedMessage,
@@ -120,18 +142,32 @@ pub struct TimedMessage {
     pub expiration: Instant,
 }
 
+impl TimedMessage {
+    pub fn message(&self) -> &OverlayMessage {
+        &self.message
+    }
+}
+
+/// Moves the oldest queued message to `active`, expiring its duration after `now`
+fn activate_next(
+    queue: &mut VecDeque<OverlayMessage>,
+    active: &mut VecDeque<TimedMessage>,
+    now: Instant,
+) {
+    if let Some(message) = queue.pop_front() {
+        let expiration = now + message.duration;
+        active.push_back(TimedMessage {
+            message,
+            expiration,
+        });
+    }
+}
+
 pub fn pop_buffer_message() {
     if let Ok(mut queue) = MESSAGE_QUEUE.lock()
-        && let Some(message) = queue.pop_front()
+        && let Ok(mut active) = ACTIVE_MESSAGES.lock()
     {
-        let expiration = Instant::now() + message.duration;
-        let timed = TimedMessage {
-            message,
-            expiration,
-        };
-        if let Ok(mut active) = ACTIVE_MESSAGES.lock() {
-            active.push_back(timed);
-        }
+        activate_next(&mut queue, &mut active, Instant::now());
     }
 }
 
@@ -148,3 +184,43 @@ pub fn get_color_for_item(item: &LocatedItem) -> FontColorCB {
         (false, false, false) => CYAN,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::font_handler::WHITE;
+
+    fn message(text: &str, duration: Duration) -> OverlayMessage {
+        OverlayMessage::new(
+            vec![MessageSegment::new(text.to_string(), WHITE)],
+            duration,
+            0.0,
+            0.0,
+            MessageType::Notification,
+        )
+    }
+
+    #[test]
+    fn messages_become_active_in_order() {
+        // Local queues, the global ones are shared with anything else showing messages
+        let mut queue = VecDeque::from([
+            message("first", Duration::from_secs(3)),
+            message("second", Duration::from_secs(5)),
+        ]);
+        let mut active = VecDeque::new();
+        let now = Instant::now();
+        activate_next(&mut queue, &mut active, now);
+        activate_next(&mut queue, &mut active, now);
+        // Nothing left, this shouldn't add anything
+        activate_next(&mut queue, &mut active, now);
+
+        let texts: Vec<&str> = active
+            .iter()
+            .map(|timed| timed.message().segments()[0].text.as_str())
+            .collect();
+        assert_eq!(texts, ["first", "second"]);
+        assert_eq!(active[0].expiration, now + Duration::from_secs(3));
+        assert_eq!(active[1].expiration, now + Duration::from_secs(5));
+        assert!(queue.is_empty());
+    }
+}